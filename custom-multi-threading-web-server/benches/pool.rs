@@ -0,0 +1,137 @@
+// 比较工作窃取的线程池和之前所有线程共享一个 Mutex<Receiver> 的线程池
+//
+// 运行：cargo bench --bench pool
+// 两个线程池都不打印任何信息，比较的只是提交和执行任务本身的开销，结果输出到标准错误
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use custom_multi_threading_web_server::ThreadPool;
+
+const THREADS: usize = 4;
+const JOBS: usize = 100_000;
+const SUBMITTERS: usize = 4;
+const ROUNDS: usize = 5;
+
+// 之前的实现：所有线程共享一个通道的接收端，每次取任务都要先获取同一把锁
+// 和 ThreadPool::new 一样不限制队列的长度
+// 原来每个任务都会打印一行，现在的线程池已经不再打印，这里也去掉，两边做的是同样的工作
+mod channel_pool {
+    use std::sync::{mpsc, Arc, Mutex};
+    use std::thread;
+
+    type Job = Box<dyn FnOnce() + Send + 'static>;
+
+    enum Message {
+        NewJob(Job),
+        Terminate,
+    }
+
+    pub struct ThreadPool {
+        workers: Vec<(usize, Option<thread::JoinHandle<()>>)>,
+        sender: mpsc::Sender<Message>,
+    }
+
+    impl ThreadPool {
+        pub fn new(size: usize) -> ThreadPool {
+            let (sender, receiver) = mpsc::channel();
+            let receiver = Arc::new(Mutex::new(receiver));
+            let workers = (0..size)
+                .map(|id| {
+                    let receiver = Arc::clone(&receiver);
+                    let thread = thread::spawn(move || loop {
+                        let message = receiver.lock().unwrap().recv().unwrap();
+                        match message {
+                            Message::NewJob(job) => job(),
+                            Message::Terminate => break,
+                        }
+                    });
+                    (id, Some(thread))
+                })
+                .collect();
+            ThreadPool { workers, sender }
+        }
+
+        pub fn execute<F>(&self, f: F)
+        where
+            F: FnOnce() + Send + 'static,
+        {
+            self.sender.send(Message::NewJob(Box::new(f))).unwrap();
+        }
+    }
+
+    impl Drop for ThreadPool {
+        fn drop(&mut self) {
+            for _ in &self.workers {
+                self.sender.send(Message::Terminate).unwrap();
+            }
+            for (_, thread) in &mut self.workers {
+                if let Some(thread) = thread.take() {
+                    thread.join().unwrap();
+                }
+            }
+        }
+    }
+}
+
+// 两个线程池只需要 execute，Drop 时都会等待所有任务执行完
+trait Pool: Sync {
+    fn execute_job(&self, f: Box<dyn FnOnce() + Send + 'static>);
+}
+
+impl Pool for ThreadPool {
+    fn execute_job(&self, f: Box<dyn FnOnce() + Send + 'static>) {
+        self.execute(f);
+    }
+}
+
+impl Pool for channel_pool::ThreadPool {
+    fn execute_job(&self, f: Box<dyn FnOnce() + Send + 'static>) {
+        self.execute(f);
+    }
+}
+
+// 从 submitters 个线程一共提交 JOBS 个只增加计数的任务，返回从开始提交到所有任务执行完（线程池被丢弃）的时间
+fn run<P: Pool>(pool: P, submitters: usize) -> Duration {
+    let counter = Arc::new(AtomicUsize::new(0));
+    let start = Instant::now();
+    thread::scope(|s| {
+        for _ in 0..submitters {
+            s.spawn(|| {
+                for _ in 0..JOBS / submitters {
+                    let counter = Arc::clone(&counter);
+                    pool.execute_job(Box::new(move || {
+                        counter.fetch_add(1, Ordering::Relaxed);
+                    }));
+                }
+            });
+        }
+    });
+    drop(pool);
+    let elapsed = start.elapsed();
+    assert_eq!(JOBS / submitters * submitters, counter.load(Ordering::Relaxed));
+    elapsed
+}
+
+// 取多轮中最快的一次，减少其他程序的干扰
+fn best_of<F: FnMut() -> Duration>(mut f: F) -> Duration {
+    (0..ROUNDS).map(|_| f()).min().unwrap()
+}
+
+fn main() {
+    for submitters in [1, SUBMITTERS] {
+        let old = best_of(|| run(channel_pool::ThreadPool::new(THREADS), submitters));
+        let new = best_of(|| run(ThreadPool::new(THREADS), submitters));
+        eprintln!(
+            "{} jobs, {} threads, {} submitter(s): mutex receiver {:?}, work stealing {:?} ({:.2}x)",
+            JOBS,
+            THREADS,
+            submitters,
+            old,
+            new,
+            old.as_secs_f64() / new.as_secs_f64()
+        );
+    }
+}
@@ -15,6 +15,8 @@ fn main() {
     let listener = TcpListener::bind("127.0.0.1:9009").unwrap();
 
     let pool = ThreadPool::new(4);
+    // 线程池本身不再打印信息，需要看到线程处理请求的过程时把事件打印出来
+    pool.set_event_hook(|event| println!("{}", event));
 
     // take 方法接收一个整数，用来限制生成的迭代器的元素数量
     // 这里我们限制只能接收两个请求，也就是说，接收完两个请求，就会退出当前的循环
@@ -32,7 +34,9 @@ fn main() {
 
 fn handle_connection(mut stream: TcpStream) {
     let mut buffer= [0; 1024];
-    stream.read(&mut buffer).unwrap();
+    // read 返回实际读取的字节数，只使用读到的部分
+    let read = stream.read(&mut buffer).unwrap();
+    let buffer = &buffer[..read];
     
     let get_method_index_path = b"GET / HTTP/1.1\r\n";
     let get_method_sleep_path = b"GET /sleep HTTP/1.1\r\n";
@@ -52,7 +56,7 @@ fn handle_connection(mut stream: TcpStream) {
 
     let res = format!("{}{}{}", status, headers, contents);
 
-    stream.write(res.as_bytes()).unwrap();
+    stream.write_all(res.as_bytes()).unwrap();
 
     stream.flush().unwrap();
 
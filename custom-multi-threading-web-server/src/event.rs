@@ -0,0 +1,39 @@
+// 线程池中发生的事件，通过 ThreadPool::set_event_hook 设置的回调接收
+// Display 输出的是之前直接打印的那些信息，回调中直接 println!("{}", event) 就可以得到和之前一样的输出
+
+use std::fmt;
+use std::time::Duration;
+
+use crate::ShutdownPolicy;
+
+/// 线程池中发生的事件，`worker` 是线程的 id
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Event {
+    /// 新建了一个线程
+    WorkerStarted { worker: usize },
+    /// 线程取到一个任务，`wait` 是任务在队列中等待的时间
+    JobStarted { worker: usize, wait: Duration },
+    /// 任务执行结束，`run` 是执行的时间
+    JobFinished { worker: usize, run: Duration, panicked: bool },
+    /// 线程空闲了 `idle` 之后退出
+    WorkerRetired { worker: usize, idle: Duration },
+    /// 线程池关闭，线程退出
+    WorkerTerminated { worker: usize },
+    /// 开始关闭线程池
+    ShutdownStarted { policy: ShutdownPolicy },
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Event::WorkerStarted { worker } => write!(f, "Worker {} started.", worker),
+            Event::JobStarted { worker, .. } => write!(f, "Worker {} got a job; executing.", worker),
+            Event::JobFinished { worker, run, panicked: false } => write!(f, "Worker {} finished a job in {:?}.", worker, run),
+            Event::JobFinished { worker, run, panicked: true } => write!(f, "Worker {} job panicked after {:?}.", worker, run),
+            Event::WorkerRetired { worker, idle } => write!(f, "Worker {} has been idle for {:?}; exiting.", worker, idle),
+            Event::WorkerTerminated { worker } => write!(f, "Worker {} was told to terminate.", worker),
+            Event::ShutdownStarted { .. } => write!(f, "Shutting down all workers."),
+        }
+    }
+}
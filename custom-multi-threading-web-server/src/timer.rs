@@ -0,0 +1,148 @@
+// 延迟任务和周期任务
+//
+// 线程池第一次收到定时任务时才新建一个定时器线程，它只负责在任务到期时把任务放入线程池的队列，任务本身还是由线程池的线程执行
+// 还没有到期的任务按到期时间放在一个小顶堆中，定时器线程在 Condvar 上等到最早的任务到期，插入更早的任务时会被提前唤醒
+// 周期任务每次执行结束之后才计算并登记下一次的到期时间，所以同一个周期任务不会同时在两个线程中执行
+
+use std::cmp::Ordering as CmpOrdering;
+use std::collections::BinaryHeap;
+use std::mem;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Instant;
+
+use crate::Job;
+
+/// `schedule_after` 和 `schedule_at_fixed_rate` 返回的句柄，用来取消任务
+///
+/// 丢弃句柄不会取消任务
+#[derive(Debug, Clone)]
+pub struct ScheduledHandle {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl ScheduledHandle {
+    pub(crate) fn new() -> ScheduledHandle {
+        ScheduledHandle { cancelled: Arc::new(AtomicBool::new(false)) }
+    }
+
+    /// 取消任务：还没有执行的延迟任务不会再执行，周期任务不会再开始新的一次执行
+    ///
+    /// 正在执行的那一次不会被中断
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    /// 是否已经被取消，周期任务 panic 之后也会被取消
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+}
+
+// 堆中的任务，到期时间相同时先登记的先执行
+struct Entry {
+    due: Instant,
+    seq: u64,
+    handle: ScheduledHandle,
+    job: Job,
+}
+
+impl PartialEq for Entry {
+    fn eq(&self, other: &Entry) -> bool {
+        (self.due, self.seq) == (other.due, other.seq)
+    }
+}
+
+impl Eq for Entry {}
+
+impl PartialOrd for Entry {
+    fn partial_cmp(&self, other: &Entry) -> Option<CmpOrdering> {
+        Some(self.cmp(other))
+    }
+}
+
+// BinaryHeap 是大顶堆，反过来比较，最早到期的任务就在堆顶
+impl Ord for Entry {
+    fn cmp(&self, other: &Entry) -> CmpOrdering {
+        (other.due, other.seq).cmp(&(self.due, self.seq))
+    }
+}
+
+struct State {
+    entries: BinaryHeap<Entry>,
+    next_seq: u64,
+    // 线程池正在关闭，不再接受新的定时任务
+    stopped: bool,
+    thread: Option<JoinHandle<()>>,
+}
+
+pub(crate) struct Timer {
+    state: Mutex<State>,
+    // 插入了新的任务，或者定时器停止了
+    changed: Condvar,
+}
+
+impl Timer {
+    pub(crate) fn new() -> Timer {
+        Timer {
+            state: Mutex::new(State { entries: BinaryHeap::new(), next_seq: 0, stopped: false, thread: None }),
+            changed: Condvar::new(),
+        }
+    }
+
+    // 登记一个在 due 到期的任务，定时器线程还不存在时用 start 新建它
+    // 定时器已经停止时任务被直接丢弃
+    pub(crate) fn insert<F>(&self, due: Instant, handle: ScheduledHandle, job: Job, start: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let mut state = self.state.lock().unwrap();
+        if state.stopped {
+            return;
+        }
+        if state.thread.is_none() {
+            let thread = thread::Builder::new()
+                .name(String::from("pool-timer"))
+                .spawn(start)
+                .expect("failed to spawn the timer thread");
+            state.thread = Some(thread);
+        }
+        let seq = state.next_seq;
+        state.next_seq += 1;
+        state.entries.push(Entry { due, seq, handle, job });
+        self.changed.notify_one();
+    }
+
+    // 定时器线程调用：阻塞直到有任务到期，返回这个任务和它的句柄，定时器停止时返回 None
+    // 被取消的任务也要等到期之后才会从堆中移除，由调用者在锁外面丢弃
+    pub(crate) fn next_due(&self) -> Option<(Job, ScheduledHandle)> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if state.stopped {
+                return None;
+            }
+            let now = Instant::now();
+            state = match state.entries.peek().map(|entry| entry.due) {
+                Some(due) if due <= now => return state.entries.pop().map(|entry| (entry.job, entry.handle)),
+                Some(due) => self.changed.wait_timeout(state, due - now).unwrap().0,
+                None => self.changed.wait(state).unwrap(),
+            };
+        }
+    }
+
+    // 停止定时器并等待定时器线程退出，还没有到期的任务都被丢弃
+    pub(crate) fn stop(&self) {
+        let (entries, thread) = {
+            let mut state = self.state.lock().unwrap();
+            state.stopped = true;
+            self.changed.notify_all();
+            (mem::take(&mut state.entries), state.thread.take())
+        };
+        // 在锁外面丢弃任务，任务中的值的 drop 可能会做任何事情
+        drop(entries);
+        if let Some(thread) = thread {
+            let _ = thread.join();
+        }
+    }
+}
@@ -2,9 +2,7 @@ use std::fs;
 use std::thread;
 use std::net::TcpListener;
 use std::net::TcpStream;
-use std::io;
 use std::io::prelude::*;
-use std::fs::File;
 use std::time::Duration;
 
 use custom_self_multi_threading_web_server::ThreadPool;
@@ -12,6 +10,8 @@ use custom_self_multi_threading_web_server::ThreadPool;
 fn main() {
     let listener = TcpListener::bind("127.0.0.1:9009").unwrap();
     let pool = ThreadPool::new(4);
+    // 线程池本身不再打印信息，需要看到线程处理请求的过程时把事件打印出来
+    pool.set_event_hook(|event| println!("{}", event));
 
     // 这里调用 take 方法，目的是生成一个指定数量元素的迭代器，这样就可以在收到指定数量的请求以后终止迭代
     for s in listener.incoming().take(2) {
@@ -31,18 +31,20 @@ fn handle_request(mut stream: TcpStream) {
 
     let mut buffer = [0; 1024];
     
-    stream.read(&mut buffer).unwrap();
+    // read 返回实际读取的字节数，只使用读到的部分
+    let read = stream.read(&mut buffer).unwrap();
+    let buffer = &buffer[..read];
 
-    println!("{}", String::from_utf8_lossy(&buffer));
+    println!("{}", String::from_utf8_lossy(buffer));
 
-    let reqText = String::from_utf8_lossy(&buffer[..]);
+    let req_text = String::from_utf8_lossy(buffer);
 
     let index_path = "GET / HTTP/1.1\r\n";
     let sleep_path = "GET /sleep HTTP/1.1\r\n";
 
-    let (status, path) = if reqText.starts_with(index_path) {
+    let (status, path) = if req_text.starts_with(index_path) {
         ("HTTP/1.1 200 OK\r\n", "./resources/index.html")
-    } else if reqText.starts_with(sleep_path) {
+    } else if req_text.starts_with(sleep_path) {
         thread::sleep(Duration::from_millis(5000));
         ("HTTP/1.1 200 OK\r\n", "./resources/sleep.html")
     } else {
@@ -57,8 +59,8 @@ fn handle_request(mut stream: TcpStream) {
 
     let res = format!("{}{}{}", status, content_length_header, content);
 
-    stream.write(res.as_bytes()).unwrap();
+    stream.write_all(res.as_bytes()).unwrap();
 
-    stream.flush();
+    stream.flush().unwrap();
 
 }
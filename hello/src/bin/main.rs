@@ -34,6 +34,8 @@ fn main() {
 
     // 创建一个可以配置线程数量的线程池，将线程池中，线程的数量配置为 4
     let pool = ThreadPool::new(4);
+    // 线程池本身不再打印信息，把事件打印出来，可以看到每个请求由哪个线程处理
+    pool.set_event_hook(|event| println!("{}", event));
 
     // 测试我们的服务器是否会在接收两个请求以后就会停机
     // 定义在 Iterator trait 中的 take 方法限制了我们的迭代过程最多只会进行两次
@@ -64,7 +66,9 @@ fn handle_connection(mut stream: TcpStream) {
     // [0; 512] 用来声明一个固定大小的数组
     let mut buffer = [0; 512];
     
-    stream.read(&mut buffer).unwrap();
+    // read 返回实际读取的字节数，只使用读到的部分
+    let read = stream.read(&mut buffer).unwrap();
+    let buffer = &buffer[..read];
 
     let get = b"GET / HTTP/1.1\r\n";
 
@@ -91,7 +95,7 @@ fn handle_connection(mut stream: TcpStream) {
     let contents = fs::read_to_string(filename).unwrap();
     // format! 格式化给定的字符串，将 html 内容与请求行拼接成完整的 http 响应
     let response = format!("{}{}", status_line, contents);
-    stream.write(response.as_bytes()).unwrap();
+    stream.write_all(response.as_bytes()).unwrap();
     stream.flush().unwrap();
     
 }
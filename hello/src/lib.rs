@@ -1,9 +1,37 @@
-use std::sync::mpsc;
-use std::thread;
+use std::error::Error;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+mod event;
+mod scheduler;
+mod scope;
+mod task;
+mod timer;
+
+pub use event::Event;
+use scheduler::{EventHook, Shared};
+pub use scope::Scope;
+pub use task::{Panicked, TaskHandle};
+pub use timer::ScheduledHandle;
+
+// 无法获取 CPU 的数量时，ThreadPoolBuilder 默认的最大线程数量
+const DEFAULT_MAX_THREADS: usize = 4;
 
+// ThreadPoolBuilder 默认的空闲线程的存活时间
+const DEFAULT_KEEP_ALIVE: Duration = Duration::from_secs(60);
 
+// shutdown 等待线程退出时，每次检查之间的间隔
+const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+// par_map 等把所有的项分成多少批提交，每个线程平均几批
+// 每一项一个任务的话排队的开销比任务本身还大，只分成和线程一样多的批又会因为某一批特别慢而让其他线程闲着
+const PAR_BATCHES_PER_THREAD: usize = 4;
+
+// 线程池
+// 每个线程有自己的任务队列，空闲的线程会从其他线程的队列中偷任务，具体的调度过程见 scheduler 模块
 pub struct ThreadPool {
     // thread 是动态数组实例，其元素的类型是：thread::JoinHandle<()>
     // 这是因为 spawn 方法的返回值就是 JoinHandle 类型
@@ -11,58 +39,644 @@ pub struct ThreadPool {
 
     // 定义一个 workers 属性
     // 用来持有 Worker 类型的动态数组
-    workers: Vec<Worker>,
+    // workers: Vec<Worker>,
     // 定义一个 sender 属性，用来持有 channel 的发送端
     // 作用是向线程中发送需要执行的代码
     // sender: mpsc::Sender<Job>
 
     // 将 Job 类型修改为 Message
-    sender: mpsc::Sender<Message>
+    // sender: mpsc::Sender<Message>
+
+    // mpsc::channel 创建的通道是无界的，连接过多时排队的任务会无限制地占用内存
+    // 改为使用 sync_channel 创建有界的通道，队列满了之后 send 会阻塞，直到有线程取走任务
+    // sender: mpsc::SyncSender<Message>,
+
+    // 所有线程共用一个通道时，每次取任务都要竞争接收端的锁，所以改为每个线程一个自己的队列
+    // workers 和队列都移到了所有线程共享的 Shared 中，线程数量会变化，线程退出时要能把自己从 workers 中移除
+    // 所有线程共享的任务队列和状态，现有的线程也记录在这里，关闭线程池时需要等待每个线程退出
+    shared: Arc<Shared>,
 }
 
-// 定义一个枚举
-enum Message {
-    // 需要运行 Job 的 NewJob 变体
-    NewJob(Job),
-    // 线程退出循环并停止的 Terminate 变体
-    Terminate
+/// 配置并创建线程池
+///
+/// 线程的数量在 `min_threads` 和 `max_threads` 之间变化：排队的任务比空闲的线程多时新建线程，
+/// 线程空闲超过 `keep_alive` 之后退出，但至少保留 `min_threads` 个线程
+///
+/// ```
+/// use std::time::Duration;
+/// use hello::ThreadPool;
+///
+/// let pool = ThreadPool::builder()
+///     .min_threads(2)
+///     .max_threads(8)
+///     .keep_alive(Duration::from_secs(30))
+///     .event_hook(|event| println!("{}", event))
+///     .build();
+/// pool.execute(|| println!("hello"));
+/// ```
+#[derive(Clone)]
+pub struct ThreadPoolBuilder {
+    min_threads: usize,
+    max_threads: usize,
+    keep_alive: Duration,
+    queue_capacity: Option<usize>,
+    event_hook: Option<EventHook>,
+}
+
+// 回调没有实现 Debug，只显示有没有设置
+impl fmt::Debug for ThreadPoolBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ThreadPoolBuilder")
+            .field("min_threads", &self.min_threads)
+            .field("max_threads", &self.max_threads)
+            .field("keep_alive", &self.keep_alive)
+            .field("queue_capacity", &self.queue_capacity)
+            .field("event_hook", &self.event_hook.is_some())
+            .finish()
+    }
+}
+
+impl Default for ThreadPoolBuilder {
+    fn default() -> ThreadPoolBuilder {
+        ThreadPoolBuilder::new()
+    }
+}
+
+impl ThreadPoolBuilder {
+    /// 默认最少 0 个线程，最多和 CPU 的数量一样多，空闲的线程 60 秒后退出
+    pub fn new() -> ThreadPoolBuilder {
+        ThreadPoolBuilder {
+            min_threads: 0,
+            max_threads: thread::available_parallelism().map(|n| n.get()).unwrap_or(DEFAULT_MAX_THREADS),
+            keep_alive: DEFAULT_KEEP_ALIVE,
+            queue_capacity: None,
+            event_hook: None,
+        }
+    }
+
+    /// 最少保留的线程数量，创建线程池时会立即创建这些线程
+    pub fn min_threads(mut self, min_threads: usize) -> ThreadPoolBuilder {
+        self.min_threads = min_threads;
+        self
+    }
+
+    /// 最多同时存在的线程数量
+    pub fn max_threads(mut self, max_threads: usize) -> ThreadPoolBuilder {
+        self.max_threads = max_threads;
+        self
+    }
+
+    /// 多于 `min_threads` 的线程空闲多久之后退出
+    pub fn keep_alive(mut self, keep_alive: Duration) -> ThreadPoolBuilder {
+        self.keep_alive = keep_alive;
+        self
+    }
+
+    /// 最多可以有多少个任务在队列中等待，默认不限制
+    pub fn queue_capacity(mut self, capacity: usize) -> ThreadPoolBuilder {
+        self.queue_capacity = Some(capacity);
+        self
+    }
+
+    /// 接收线程池事件的回调，在创建最初的线程之前设置，所以也能收到这些线程的 `Event::WorkerStarted`
+    pub fn event_hook<H>(mut self, hook: H) -> ThreadPoolBuilder
+    where
+        H: Fn(&Event) + Send + Sync + 'static,
+    {
+        self.event_hook = Some(Arc::new(hook));
+        self
+    }
+
+    /// 创建线程池
+    ///
+    /// # panic
+    ///
+    /// `max_threads` 为 0，或者 `min_threads` 大于 `max_threads` 时触发 panic
+    pub fn build(self) -> ThreadPool {
+        assert!(self.max_threads > 0);
+        assert!(self.min_threads <= self.max_threads);
+
+        // with_capacity 方法创建一个指定长度的数组
+        // with_capacity 与 Vec::new 有些类似，但区别在于 with_capacity 会为动态数组预分配出指定的空间
+        // 在知晓存储大小的前提下预先分配存储空 间要比使用 Vec::new 在插入时动态扩展大小更有效率一些
+        // let mut threads = Vec::with_capacity(size);
+        // let mut workers = Vec::with_capacity(size);
+
+        // 我们希望刚刚创建的Worker结构体能够从存储在ThreadPool的队列中获取需要执行的代码，并将它们发送到线程中运行
+        // 因此我们使用 channel 作为线程通信的方式
+        // 将我们需要线程执行的代码发送给线程，也就是 Worker 实例
+        // 创建一个 channel，并持有发送端 sender
+        // let (sender, receiver) = mpsc::channel();
+
+        // 有界的通道，最多缓存 capacity 个消息
+        // let (sender, receiver) = mpsc::sync_channel(capacity);
+
+        // 为了在多个线程中共享所有权并允许线程修改共享值，我们可以使用 Arc<Mutex<T>>
+        // Arc 类型允许多个工作线程拥有同一个接收者，而 Mutex 则保证了一次只有一个工作线程能够从接收端得到任务
+        // let receiver = Arc::new(Mutex::new(receiver));
+
+        // 现在不再使用通道，每个线程的队列都放在 Shared 中，每个队列各自使用一个 Mutex
+        // 所有线程依然通过 Arc 共享同一个 Shared 的所有权
+        let shared = Arc::new(Shared::new(self.min_threads, self.max_threads, self.queue_capacity, self.keep_alive));
+        if let Some(hook) = self.event_hook {
+            shared.set_event_hook(hook);
+        }
+        for _ in 0..self.min_threads {
+            // 创建线程并将其存储到动态数组
+
+            // 生成的每个 Worker 实例都会持有 channel 的接收端 receiver
+            // 如果我们将 receiver 直接传递给多个 Worker 实例，那么编译会失败：
+            // error[E0382]: use of moved value: `receiver`
+            // 直接传递给线程，会发生所有权的转移，因此，多个线程无法直接使用 receiver
+            // 因为 Rust 提供的 channel 是多生产者、单消费者的，这也意味着你不能简单地通过克隆接收端来解决上述问题
+            // 我们希望所有的线程都使用这一个消费者从而能够在线程间分发任务
+            // workers.push(Worker::new(id, receiver))
+
+            // 创建新的 Worker 时克隆Arc来增加引用计数
+            // 从而使所有的工作线程可以共享接收端的所有权
+            // workers.push(Worker::new(id, Arc::clone(&receiver), Arc::clone(&shared)));
+
+            // 现在由 spawn_worker 克隆 Arc<Shared> 并创建线程，新的 Worker 同样记录在 Shared 中
+            shared.spawn_worker();
+        }
+
+        ThreadPool {
+            // threads
+            // workers,
+            // sender,
+            shared,
+        }
+    }
 }
 
+// 定义一个枚举
+// enum Message {
+//     // 需要运行 Job 的 NewJob 变体
+//     NewJob(Job),
+//     // 线程退出循环并停止的 Terminate 变体
+//     Terminate
+// }
+// 现在任务直接放入队列，关闭线程池时也不再发送 Terminate，而是通知所有线程线程池正在关闭，所以不再需要 Message
+
 // struct Job {
 
 // }
 
 // Job 类型作为 Box 指针的类型别名，用来持有闭包
-type Job = Box<dyn FnOnce() + Send + 'static>;
+// try_execute 失败时会把任务还给调用者，所以 Job 需要是公开的
+pub type Job = Box<dyn FnOnce() + Send + 'static>;
 
-impl Drop for ThreadPool {
+// try_execute 无法提交任务时返回的错误，错误中带着原来的任务，调用者可以稍后重试，或者自己执行它
+pub enum TryExecuteError {
+    // 队列已满
+    Full(Job),
+    // 线程池正在关闭，任务永远不会被执行
+    Disconnected(Job),
+}
+
+impl TryExecuteError {
+    // 取回没有提交成功的任务
+    pub fn into_job(self) -> Job {
+        match self {
+            TryExecuteError::Full(job) | TryExecuteError::Disconnected(job) => job,
+        }
+    }
+}
 
+// 闭包没有实现 Debug，所以这里手动实现，只输出错误的类型
+impl fmt::Debug for TryExecuteError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TryExecuteError::Full(_) => write!(f, "Full(..)"),
+            TryExecuteError::Disconnected(_) => write!(f, "Disconnected(..)"),
+        }
+    }
+}
 
-    // 为 ThreadPool 实现 Drop 方法
-    // 用池中每个线程的 join 方法，从而使它们能够在关闭前完成当前正在处理的工作
-    fn drop(&mut self) {
+impl fmt::Display for TryExecuteError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TryExecuteError::Full(_) => write!(f, "the job queue is full"),
+            TryExecuteError::Disconnected(_) => write!(f, "all workers have stopped"),
+        }
+    }
+}
 
+impl Error for TryExecuteError {}
 
-        println!("Sending terminate message to all workers.");
+/// 任务的优先级，见 `ThreadPool::execute_with_priority`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Priority {
+    /// 和 `execute` 提交的任务一样
+    #[default]
+    Normal,
+    /// 空闲的线程总是先执行高优先级的任务，然后才是普通的任务
+    High,
+}
 
-        // 第一次循环次向每个 worker 发送了 Terminate 消息
-        for _ in &mut self.workers {
-            self.sender.send(Message::Terminate).unwrap();
+/// 关闭线程池时如何处理队列中还没有开始执行的任务
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShutdownPolicy {
+    /// 执行完队列中所有的任务之后再退出
+    Drain,
+    /// 丢弃队列中的任务，线程执行完手上正在执行的任务就退出
+    Abort,
+}
+
+/// 线程池当前的统计数据，见 `ThreadPool::stats`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// 现有的线程数量
+    pub threads: usize,
+    /// 在队列中等待的任务数量
+    pub queued: usize,
+    /// 正在执行的任务数量
+    pub active: usize,
+    /// 执行结束的任务数量，包括 panic 的任务
+    pub completed: usize,
+    /// panic 的任务数量
+    pub panicked: usize,
+    /// 执行结束的任务平均在队列中等待的时间
+    pub average_wait: Duration,
+    /// 执行结束的任务平均的执行时间
+    pub average_run: Duration,
+}
+
+/// `shutdown` 的结果，列出的都是线程的 id
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ShutdownReport {
+    /// 在超时之前退出的线程
+    pub joined: Vec<usize>,
+    /// 超时的时候还在执行任务的线程，线程池不再等待它们，它们会在任务结束之后自己退出
+    pub timed_out: Vec<usize>,
+}
+
+impl ShutdownReport {
+    /// 所有线程都在超时之前退出了
+    pub fn is_complete(&self) -> bool {
+        self.timed_out.is_empty()
+    }
+}
+
+impl ThreadPool {
+    /// 创建固定数量线程的线程池
+    ///
+    /// 线程池中线程的数量
+    ///
+    /// # panic
+    ///
+    /// `new` 函数会在 size 为 0 时触发 panic
+    ///
+    /// 队列的容量不限制，和之前使用 channel 时一样，需要限制时使用 `with_queue_capacity`，线程数量需要变化时使用 `builder`
+    pub fn new(size: usize) -> ThreadPool {
+        // 负的线程数量没有任何意义，因此这里选择了 usize 作为 size 参数的类型
+
+        // 使用 assert! 宏进行断言，当 size 为 0 时，触发程序的 panic
+        // 我们也可以在 new 函数中返回一个 Result 而不再使用 assert! 宏
+        assert!(size > 0);
+
+        ThreadPool::builder().min_threads(size).max_threads(size).build()
+    }
+
+    /// 创建线程池，并指定所有线程的队列中最多一共可以有多少个任务在等待
+    ///
+    /// 队列满了之后，`execute` 会阻塞，`try_execute` 会返回错误
+    /// 线程池的任务提交新任务时不会阻塞，而是先在当前线程中执行排队的任务，直到空出位置
+    /// capacity 为 0 时按 1 处理
+    ///
+    /// # panic
+    ///
+    /// size 为 0 时触发 panic
+    pub fn with_queue_capacity(size: usize, capacity: usize) -> ThreadPool {
+        assert!(size > 0);
+
+        ThreadPool::builder()
+            .min_threads(size)
+            .max_threads(size)
+            .queue_capacity(capacity)
+            .build()
+    }
+
+    /// 配置线程数量的范围、空闲线程的存活时间等，见 `ThreadPoolBuilder`
+    pub fn builder() -> ThreadPoolBuilder {
+        ThreadPoolBuilder::new()
+    }
+
+    /// 现有的线程数量
+    pub fn thread_count(&self) -> usize {
+        self.shared.thread_count()
+    }
+
+    /// 接收一个闭包，并将其发到某个线程上执行
+    ///
+    /// 设置了队列容量时，队列满了会一直阻塞，直到某个线程取走一个任务，这样提交任务的一方就会自然地放慢速度
+    /// 在线程池的任务中调用时，新任务会放入当前线程自己的队列，队列满了时先执行排队的任务，不会阻塞
+    // 仿照 Thread::spawn 方法的签名构造 execute 方法
+    // pub fn spawn<F, T>(f: F) -> JoinHandle<T>
+    // where
+    // F: FnOnce() -> T + Send + 'static,
+    // T: Send + 'static
+    // 因为 execute 接收的闭包最终要传入 spawn 方法中
+    // 而请求处理的线程只会执行一次闭包，所以这里就是使用 FnOnce 这个 trait 约束闭包的类型
+    // 同时类型参数 F 还需要满足 Send trait 和 生命周期 'static
+    // 因为满足 Send trait 约束的闭包才可以从一个线程传递到另一个线程
+    // 而由于我们不知道线程究竟会执行多长时间，所以闭包必须是 'static 的
+    // FnOnce 后的 () 意味着传入的闭包既没有参数，也不返回结果
+    // 就像函数定义一样，我们可以省略签名中的返回值，但却不能省略函数名后的圆括号，即便括号中没有任何参数
+    pub fn execute<F>(&self, f: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.execute_with_priority(Priority::Normal, f);
+    }
+
+    /// 按指定的优先级提交任务
+    ///
+    /// 高优先级的任务放入所有线程共享的队列，会比已经在排队的普通任务先开始执行，适合对延迟敏感的请求
+    /// 正在执行的任务不会被打断，队列已满时和 `execute` 一样阻塞
+    pub fn execute_with_priority<F>(&self, priority: Priority, f: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.shared.reserve();
+        // 接收到闭包后会创建出一个新的Job实例，使用 Box 指针包裹一个函数类型
+        // let job = Box::new(f);
+        // 将这个任务传递给通道的发送端，为了应对发送失败的情形，我们在 send 后直接调用了 unwrap
+        // self.sender.send(job).unwrap();
+
+        // 通过通道发送的不再是 job 实例
+        // 而是发送 Message 枚举的 NewJob 变体
+        // self.sender.send(Message::NewJob(job)).unwrap();
+
+        // 现在任务直接放入某个线程的队列，已经预留了位置，放入队列不会失败
+        self.shared.push(Box::new(f), priority);
+    }
+
+    /// 在 delay 之后执行任务，可以通过返回的句柄在执行之前取消
+    ///
+    /// 到期的任务和普通的任务一样排队，关闭线程池时还没有到期的任务会被丢弃
+    pub fn schedule_after<F>(&self, delay: Duration, f: F) -> ScheduledHandle
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let handle = ScheduledHandle::new();
+        // delay 太大算不出到期时间时，任务永远不会执行
+        if let Some(due) = Instant::now().checked_add(delay) {
+            self.shared.schedule(due, handle.clone(), Box::new(f));
+        }
+        handle
+    }
+
+    /// 每隔 period 执行一次任务，第一次在 period 之后执行，直到通过返回的句柄取消或者线程池关闭
+    ///
+    /// 上一次执行结束之后才会开始下一次，执行时间超过 period 时下一次会推迟，但不会同时执行两次
+    /// 任务 panic 之后不再执行，句柄变为已取消
+    ///
+    /// # panic
+    ///
+    /// period 为 0 时触发 panic
+    pub fn schedule_at_fixed_rate<F>(&self, period: Duration, f: F) -> ScheduledHandle
+    where
+        F: FnMut() + Send + 'static,
+    {
+        assert!(!period.is_zero());
+
+        self.shared.schedule_at_fixed_rate(period, f)
+    }
+
+    /// 和 `execute` 一样提交任务，但是队列满了的时候不会阻塞，而是立即把任务放在错误中返回
+    ///
+    /// 例如 Web 服务器可以在繁忙时直接拒绝新的连接，而不是让它们一直等待
+    pub fn try_execute<F>(&self, f: F) -> Result<(), TryExecuteError>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        if self.shared.is_shutting_down() {
+            return Err(TryExecuteError::Disconnected(Box::new(f)));
         }
+        if !self.shared.try_reserve() {
+            return Err(TryExecuteError::Full(Box::new(f)));
+        }
+        self.shared.push(Box::new(f), Priority::Normal);
+        Ok(())
+    }
 
-        println!("Shutting down all workers.");
+    /// 提交一个有返回值的任务，通过返回的 `TaskHandle` 等待结果
+    ///
+    /// 任务和 `execute` 提交的任务一样在队列中排队，任务 panic 时 `join` 返回 `Panicked`
+    pub fn spawn<F, T>(&self, f: F) -> TaskHandle<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let (job, handle) = task::task(f);
+        self.shared.reserve();
+        self.shared.push(job, Priority::Normal);
+        handle
+    }
+
+    /// 创建一个作用域，作用域中提交的任务可以借用局部变量，`scope` 返回之前这些任务都已经结束了
+    ///
+    /// 和 `std::thread::scope` 一样，只是任务由线程池的线程执行，不会新建线程
+    /// 在线程池的任务中调用时，等待的线程会帮忙执行队列中的任务，不会因为所有线程都在等待而卡住
+    ///
+    /// 任何一个任务 panic 时，`scope` 会在所有任务结束之后继续这个 panic
+    ///
+    /// ```
+    /// use hello::ThreadPool;
+    ///
+    /// let pool = ThreadPool::new(4);
+    /// let mut numbers = vec![1, 2, 3, 4];
+    /// let offset = 10;
+    /// pool.scope(|s| {
+    ///     for n in numbers.iter_mut() {
+    ///         s.execute(|| *n += offset);
+    ///     }
+    /// });
+    /// assert_eq!(vec![11, 12, 13, 14], numbers);
+    /// ```
+    pub fn scope<'env, F, R>(&self, f: F) -> R
+    where
+        F: for<'scope> FnOnce(&'scope Scope<'scope, 'env>) -> R,
+    {
+        scope::scope(&self.shared, f)
+    }
+
+    /// 在线程池中对每一项执行 f，按原来的顺序返回结果
+    ///
+    /// 所有的项先被收集起来，再分成若干批提交，每批是一个任务，调用者会一直等到所有的项都执行完
+    /// 任何一项 panic 时，`par_map` 在其他任务结束之后继续这个 panic；需要返回错误时使用 `try_par_map`
+    ///
+    /// ```
+    /// use hello::ThreadPool;
+    ///
+    /// let pool = ThreadPool::new(4);
+    /// let squares = pool.par_map(1..=4, |n| n * n);
+    /// assert_eq!(vec![1, 4, 9, 16], squares);
+    /// ```
+    pub fn par_map<I, F, R>(&self, iter: I, f: F) -> Vec<R>
+    where
+        I: IntoIterator,
+        I::Item: Send,
+        F: Fn(I::Item) -> R + Sync,
+        R: Send,
+    {
+        let mut items = iter.into_iter().collect::<Vec<_>>().into_iter();
+        let mut results: Vec<Option<R>> = (0..items.len()).map(|_| None).collect();
+        let batch_size = items.len().div_ceil(self.shared.max_threads() * PAR_BATCHES_PER_THREAD).max(1);
+        let f = &f;
+        self.scope(|s| {
+            // 每批任务拿走自己的那些项，结果直接写到 results 中对应的位置，所以顺序不会乱
+            for slots in results.chunks_mut(batch_size) {
+                let batch: Vec<_> = items.by_ref().take(slots.len()).collect();
+                s.execute(move || {
+                    for (slot, item) in slots.iter_mut().zip(batch) {
+                        *slot = Some(f(item));
+                    }
+                });
+            }
+        });
+        // 没有任何一项 panic 时 scope 才会返回，这时每个位置都已经有了结果
+        results.into_iter().map(Option::unwrap).collect()
+    }
+
+    /// 和 `par_map` 一样执行，但是 f 返回错误时，还没有开始的项不再执行，并返回按顺序第一个遇到的错误
+    pub fn try_par_map<I, F, R, E>(&self, iter: I, f: F) -> Result<Vec<R>, E>
+    where
+        I: IntoIterator,
+        I::Item: Send,
+        F: Fn(I::Item) -> Result<R, E> + Sync,
+        R: Send,
+        E: Send,
+    {
+        let failed = AtomicBool::new(false);
+        let results = self.par_map(iter, |item| {
+            if failed.load(Ordering::Relaxed) {
+                return None;
+            }
+            let result = f(item);
+            if result.is_err() {
+                failed.store(true, Ordering::Relaxed);
+            }
+            Some(result)
+        });
+        // 被跳过的项是 None，只有在某一项出错之后才会出现，这时一定能遇到那个错误
+        let mut values = Vec::with_capacity(results.len());
+        for result in results.into_iter().flatten() {
+            values.push(result?);
+        }
+        Ok(values)
+    }
+
+    /// 在线程池中对每一项执行 f，所有的项都执行完之后返回，panic 的处理和 `par_map` 一样
+    pub fn par_for_each<I, F>(&self, iter: I, f: F)
+    where
+        I: IntoIterator,
+        I::Item: Send,
+        F: Fn(I::Item) + Sync,
+    {
+        self.par_map(iter, f);
+    }
+
+    /// 把 slice 按每 chunk_size 个元素分成若干块，在线程池中对每一块执行 f，按块的顺序返回结果
+    ///
+    /// 最后一块可能不足 chunk_size 个元素，panic 的处理和 `par_map` 一样
+    ///
+    /// # panic
+    ///
+    /// chunk_size 为 0 时触发 panic
+    pub fn par_chunks<T, F, R>(&self, slice: &[T], chunk_size: usize, f: F) -> Vec<R>
+    where
+        T: Sync,
+        F: Fn(&[T]) -> R + Sync,
+        R: Send,
+    {
+        assert!(chunk_size > 0);
+
+        self.par_map(slice.chunks(chunk_size), f)
+    }
+
+    /// 关闭线程池，最多等待 timeout
+    ///
+    /// `ShutdownPolicy::Drain` 会先执行完队列中的任务，`ShutdownPolicy::Abort` 会丢弃还没有开始执行的任务，
+    /// 被丢弃的 `spawn` 任务的 `join` 会返回 `Panicked`，还没有到期的延迟任务和周期任务总是被丢弃
+    ///
+    /// 正在执行的任务无法被中断，超时的时候还没有退出的线程会出现在 `ShutdownReport::timed_out` 中
+    pub fn shutdown(mut self, policy: ShutdownPolicy, timeout: Duration) -> ShutdownReport {
+        self.shutdown_workers(policy, Some(Instant::now() + timeout))
+    }
+
+    /// 到目前为止 panic 的任务的数量，包括 `spawn` 提交的任务
+    pub fn panic_count(&self) -> usize {
+        self.shared.panic_count()
+    }
+
+    /// 设置任务 panic 时调用的回调，参数是执行任务的线程的 id 和 panic 的信息，会替换之前设置的回调
+    ///
+    /// 回调在执行任务的线程中调用
+    pub fn set_panic_handler<H>(&self, handler: H)
+    where
+        H: Fn(usize, &Panicked) + Send + Sync + 'static,
+    {
+        self.shared.set_panic_handler(Box::new(handler));
+    }
+
+    /// 线程池当前的统计数据
+    ///
+    /// 各项数据是分别读取的，线程池繁忙时它们之间可能不完全一致
+    pub fn stats(&self) -> Stats {
+        self.shared.stats()
+    }
+
+    /// 设置接收线程池事件的回调，会替换之前设置的回调
+    ///
+    /// 线程池本身不再打印任何信息，需要之前的输出时可以设置 `|event| println!("{}", event)`
+    /// 回调在产生事件的线程中调用，应该尽快返回；回调 panic 时这次事件会被忽略
+    pub fn set_event_hook<H>(&self, hook: H)
+    where
+        H: Fn(&Event) + Send + Sync + 'static,
+    {
+        self.shared.set_event_hook(Arc::new(hook));
+    }
+}
+
+// 为 ThreadPool 实现 Drop 方法
+// 用池中每个线程的 join 方法，从而使它们能够在关闭前完成当前正在处理的工作
+// 当 ThreadPool 实例离开作用域时，和 shutdown 使用同样的关闭过程，但是会一直等待，直到队列中所有的任务都执行完
+impl Drop for ThreadPool {
+    fn drop(&mut self) {
+        self.shutdown_workers(ShutdownPolicy::Drain, None);
+    }
+}
+
+impl ThreadPool {
+    // 关闭线程池，deadline 为 None 时一直等待
+    // 已经被 shutdown 关闭的线程池，之后的 Drop 什么也不做
+    fn shutdown_workers(&mut self, policy: ShutdownPolicy, deadline: Option<Instant>) -> ShutdownReport {
+        let mut report = ShutdownReport::default();
+        if self.shared.is_shutting_down() {
+            return report;
+        }
+
+        self.shared.emit(Event::ShutdownStarted { policy });
+        // 第一次循环次向每个 worker 发送了 Terminate 消息
+        // for _ in &self.workers {
+        //     self.sender.send(Message::Terminate).unwrap();
+        // }
+
+        // 现在不再发送 Terminate，而是设置 shutting_down 并唤醒所有休眠的线程，它们会在队列空了之后退出
+        self.shared.begin_shutdown(policy == ShutdownPolicy::Abort);
+        // 停止定时器，之后不会再有到期的任务放入队列
+        self.shared.stop_timer();
 
         // 首先遍历了线程池中所有的workers
-        // 这里使用了 &mut，因为我们需要修改 worker 且正好 self 本身是一个可变引用
-        // 针对遍历中的每一个 worker，代码会打印出信息来表明当前的 worker 正在停止运行
-        // 并会接着在它的线程上调用 join
-        // 假如 join 调用失败，随后的 unwrap 就会触发 panic 并进入不那么优雅的关闭过程
+        // 针对遍历中的每一个 worker，代码会接着在它的线程上调用 join
+        // 之前 join 调用失败时，随后的 unwrap 就会触发 panic 并进入不那么优雅的关闭过程，现在任务的 panic 都在线程中被捕获了
         //
         // 第二次则在每个 worker 的线程上调用了 join
         // 如果我们尝试在同一个循环中发送消息并立即调用 join，那么就无法保证当前正在迭代的 worker 就是从通道中获得消息的那一个
-        for worker in &mut self.workers {
-            println!("Shutting down worker {}", worker.id);
-            // 直接调用 join 方法会报错
+        for worker in self.shared.take_workers() {
+            // 之前的 workers 属于 ThreadPool，循环中只能拿到 worker 的可变借用，直接调用 join 方法会报错
             // error[E0507]: cannot move out of `worker.thread` which is behind a mutable reference
             //     --> src/lib.rs:26:13
             //     |
@@ -76,16 +690,20 @@ impl Drop for ThreadPool {
             // 如果 Worker 持有的是一个 Option<thread::JoinHandle<()>>
             // 那么我们就可以在 Option 上调用 take 方法来将 Some 变体的值移出来
             // 并在原来的位置留下 None 变体
-            // 换句话说，正在运行中的 Worker 会在 thread 中持有一个 Some 变体
-            // 当我们希望清理 Worker 时，就可以使用 None 来替换掉 Some，从而使 Worker 失去可以运行的线程
-            // worker.thread.join().unwrap();
+            // if let Some(thread) = worker.thread.take() {
+            //     thread.join().unwrap();
+            // }
 
-
-            // 为 Option 值调用 take 方法会将 Some 变体的值移出并在原来的位置留下None变体
-            // 使用了 if let 来解构 Some 从而得到线程，并接着在这个线程上调用了 join
-            // 当某个 Worker 的线程值是 None 时，我们就知道 worker 已经清理了这个线程而无须进行任何操作
-            if let Some(thread) = worker.thread.take() {
-                thread.join().unwrap();
+            // 现在 take_workers 把所有的 Worker 从 Shared 中移了出来，循环拿到的是 worker 的所有权
+            // 可以直接在 worker.thread 上调用 join，不再需要 Option 和 take
+            //
+            // JoinHandle 没有可以指定超时的 join，所以先等到线程结束之后再调用 join
+            if wait_until_finished(&worker.thread, deadline) {
+                // 任务的 panic 已经在线程中被捕获了，这里的 join 不会再返回错误
+                let _ = worker.thread.join();
+                report.joined.push(worker.id);
+            } else {
+                report.timed_out.push(worker.id);
             }
         }
 
@@ -97,159 +715,423 @@ impl Drop for ThreadPool {
         // 为了阻止这种情况的发生，我们首先用一个循环把全部 Terminate 消息发送到通道中，随后再到另一个循环中等待所有的进程结束
         // 由于 worker 会在收到结束信号后停止接收请求，所以只要我们在调用 join 之前发送了与 workers 数目相等的结束消息
         // 就可以确保每一个  worker 都能够收到自己的结束信号
+
+        // 现在所有线程看到的是同一个 shutting_down，结束信号不会再被某一个线程截取
+        // 但依然要先通知所有线程，再逐个调用 join，否则正在休眠的线程不会醒来，join 会一直等下去
+
+        report
     }
 }
 
+// 等待线程结束，超过 deadline 时返回 false
+fn wait_until_finished(thread: &thread::JoinHandle<()>, deadline: Option<Instant>) -> bool {
+    // 没有 deadline 时直接交给 join 阻塞等待
+    let Some(deadline) = deadline else {
+        return true;
+    };
+    while !thread.is_finished() {
+        if Instant::now() >= deadline {
+            return false;
+        }
+        thread::sleep(SHUTDOWN_POLL_INTERVAL);
+    }
+    true
+}
 
-// 为什么需要一个单独的 Worker 类型呢
-// 因为按照我们之前的设定，我们使用 thread::spawn 创建线程，spawn 方法接收一个闭包作为参数
-// spawn 方法在创建完线程以后会立即执行自己接收到的代码参数
-// 但是实际上，我们创建完线程以后，不需要立即执行，而是进入等待状态并执行随后传给它的代码
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+    use std::panic::{self, AssertUnwindSafe};
+    use std::sync::mpsc::channel;
 
-// 因此我们需要创建 Worker 类，作为中间类型
-// Worker 类型持有线程（JoinHandle）
+    #[test]
+    fn bounded_queue() {
+        let pool = ThreadPool::with_queue_capacity(1, 1);
+        let (started_tx, started_rx) = channel();
+        let (release_tx, release_rx) = channel::<()>();
 
-// Worker 类型持有一个 id 和 thread
-// thread 属性用来持有 JoinHandle，也就是一个线程
-struct Worker {
-    id: usize,
-    // thread: thread::JoinHandle<()>
-    thread: Option<thread::JoinHandle<()>>
+        // 唯一的线程被第一个任务占住，第二个任务在队列中等待，队列已经满了
+        pool.execute(move || {
+            started_tx.send(()).unwrap();
+            release_rx.recv().unwrap();
+        });
+        started_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        let (done_tx, done_rx) = channel();
+        let tx = done_tx.clone();
+        pool.execute(move || tx.send(1).unwrap());
 
-}
+        // 队列满了，任务被原样还回来，调用者可以自己执行它
+        let tx = done_tx.clone();
+        let err = pool.try_execute(move || tx.send(2).unwrap()).unwrap_err();
+        assert!(matches!(err, TryExecuteError::Full(_)));
+        err.into_job()();
+        assert_eq!(2, done_rx.recv().unwrap());
 
-impl Worker {
-    // fn new(id: usize, receiver: Arc<Mutex<mpsc::Receiver<Job>>>) -> Worker {
-    // 将 receiver 的类型中的 Job 修改为 Message
-    fn new(id: usize, receiver: Arc<Mutex<mpsc::Receiver<Message>>>) -> Worker {
-        let thread = thread::spawn(move || {
-            // println!("initiate work {}", id);
-            loop {
-                // println!("received work {}", id);
+        release_tx.send(()).unwrap();
+        assert_eq!(1, done_rx.recv_timeout(Duration::from_secs(5)).unwrap());
+    }
 
+    #[test]
+    fn nested_execute() {
+        // 唯一的线程中的任务提交的任务比队列的容量多，不会因为等待自己取走任务而卡住
+        for pool in [ThreadPool::new(1), ThreadPool::with_queue_capacity(1, 1)] {
+            let pool = Arc::new(pool);
+            let inner = Arc::clone(&pool);
+            let (tx, rx) = channel();
+            pool.execute(move || {
+                for i in 0..20 {
+                    let tx = tx.clone();
+                    inner.execute(move || tx.send(i).unwrap());
+                }
+                drop(inner);
+            });
+            let mut received: Vec<i32> = (0..20).map(|_| rx.recv_timeout(Duration::from_secs(5)).unwrap()).collect();
+            received.sort();
+            assert_eq!((0..20).collect::<Vec<_>>(), received);
+        }
+    }
 
-                // 首先调用了 receiver 的 lock 方法来请求互斥锁
-                // 并接着使用 unwrap 来处理可能出现的错误情形
-                // 请求获取锁的操作会在互斥体被污染时出错，而互斥体会在某个持有锁的线程崩溃而锁没有被正常释放时被污染
-                // 在这种情形下，调用 unwrap 触发当前线程的 panic 是非常恰当的行为
-                // 当然，你也可以将 unwrap 修改为 expect 来附带一个有意义的错误提示信息
+    #[test]
+    fn spawn_and_join() {
+        let pool = ThreadPool::new(2);
+        assert_eq!(6, pool.spawn(|| 1 + 2 + 3).join().unwrap());
 
-                // 在互斥体上得到锁以后，我们就可以通过调用 recv 来从通道中接收 Job 了
-                // 与发送端的 send 方法类似，recv会在持有通道发送端的线程关闭时出现错误，所以我们同样使用了 unwrap 来拦截所有错误
-                // 调用 recv 会阻塞当前线程，当通道中不存在任务时，当前线程就会一直处于等待状态。而 Mutex<T> 则保证了一次只有一个 Worker 线程尝试请求任务
+        // 任务 panic 时，join 得到带着 panic 信息的错误
+        let err = pool.spawn(|| -> i32 { panic!("boom {}", 1) }).join().unwrap_err();
+        assert_eq!("boom 1", err.message());
 
-                // let job = receiver.lock().unwrap().recv().unwrap();
-                let message = receiver.lock().unwrap().recv().unwrap();
-                
-                // 因为 execute 方法会发送包裹着任务的 Message:NewJob 变体
-                // Worker::new 中的代码会从通道中接收并处理 Message，
-                // 并在收到 NewJob 变体时处理任务，在收到 Terminate 变体时退出循环
-                match message {
-                    Message::NewJob(job) => {
-                        println!("Worker {} got a job; executing.", id);
-                        job();
-                    },
-                    Message::Terminate => {
-                        println!("Worker {} was told to terminate.", id);
-                        break;
-                    }
-                }
+        let (release_tx, release_rx) = channel::<()>();
+        let mut handle = pool.spawn(move || {
+            release_rx.recv().unwrap();
+            "done"
+        });
+        assert!(handle.try_join().is_none());
+        assert!(handle.join_timeout(Duration::from_millis(20)).is_none());
+        release_tx.send(()).unwrap();
+        assert_eq!("done", handle.join_timeout(Duration::from_secs(5)).unwrap().unwrap());
+        // 结果已经被取走
+        assert!(handle.try_join().is_none());
+    }
 
-                // println!("Worker {} got a job; executing.", id);
-                // job();
+    #[test]
+    fn survives_panics() {
+        let pool = ThreadPool::new(1);
+        let (tx, rx) = channel();
+        pool.set_panic_handler(move |id, panicked| tx.send((id, panicked.message().to_string())).unwrap());
 
-                // 通过使用 loop 并在循环代码块内部而不是外部请求锁和任务， lock 方法中返回的 MutexGuard 会在 let job 语句结束后被立即丢弃
-                // 这确保了我们只会在调用 recv 的过程中持有锁，并能够在调用 job()之前将锁释放。因此，我们的服务器才可以同时响应多个请求
-            }
+        pool.execute(|| panic!("first"));
+        assert!(pool.spawn(|| panic!("second")).join().is_err());
+        // 唯一的线程依然可以继续执行任务
+        assert_eq!(1, pool.spawn(|| 1).join().unwrap());
+
+        assert_eq!(2, pool.panic_count());
+        assert_eq!((0, String::from("first")), rx.recv().unwrap());
+        assert_eq!((0, String::from("second")), rx.recv().unwrap());
+    }
+
+    #[test]
+    fn shutdown_policies() {
+        let (tx, rx) = channel();
+        let pool = ThreadPool::new(2);
+        for i in 0..8 {
+            let tx = tx.clone();
+            pool.execute(move || {
+                thread::sleep(Duration::from_millis(5));
+                tx.send(i).unwrap();
+            });
+        }
+        let report = pool.shutdown(ShutdownPolicy::Drain, Duration::from_secs(5));
+        assert!(report.is_complete());
+        assert_eq!(vec![0, 1], report.joined);
+        assert_eq!(8, rx.try_iter().count());
+
+        // 唯一的线程被占住，队列中的任务会被丢弃
+        let pool = ThreadPool::new(1);
+        let (release_tx, release_rx) = channel::<()>();
+        let (started_tx, started_rx) = channel();
+        pool.execute(move || {
+            started_tx.send(()).unwrap();
+            release_rx.recv().unwrap();
         });
+        started_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        let dropped = pool.spawn(|| 1);
+        let report = pool.shutdown(ShutdownPolicy::Abort, Duration::from_millis(50));
+        assert_eq!(vec![0], report.timed_out);
+        release_tx.send(()).unwrap();
+        assert!(dropped.join().is_err());
+    }
+
+    #[test]
+    fn grows_and_shrinks() {
+        let pool = ThreadPool::builder()
+            .min_threads(1)
+            .max_threads(3)
+            .keep_alive(Duration::from_millis(50))
+            .build();
+        assert_eq!(1, pool.thread_count());
+
+        // 三个任务同时阻塞，排队的任务让线程池新建线程，直到达到 max_threads
+        let (release_tx, release_rx) = channel::<()>();
+        let release_rx = Arc::new(std::sync::Mutex::new(release_rx));
+        let handles: Vec<_> = (0..3)
+            .map(|_| {
+                let release_rx = Arc::clone(&release_rx);
+                pool.spawn(move || release_rx.lock().unwrap().recv().unwrap())
+            })
+            .collect();
+        thread::sleep(Duration::from_millis(20));
+        assert_eq!(3, pool.thread_count());
+        for _ in 0..3 {
+            release_tx.send(()).unwrap();
+        }
+        for handle in handles {
+            handle.join().unwrap();
+        }
 
-         Worker {
-             id,
-             thread: Some(thread)
-         }
+        // 空闲超过 keep_alive 之后，多出来的线程退出，只保留 min_threads 个
+        let start = Instant::now();
+        while pool.thread_count() > 1 && start.elapsed() < Duration::from_secs(5) {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(1, pool.thread_count());
 
+        // 新建的线程使用新的 id
+        let (tx, rx) = channel();
+        pool.set_panic_handler(move |id, _| tx.send(id).unwrap());
+        for _ in 0..3 {
+            pool.execute(|| {
+                thread::sleep(Duration::from_millis(20));
+                panic!("record the worker id");
+            });
+        }
+        let report = pool.shutdown(ShutdownPolicy::Drain, Duration::from_secs(5));
+        assert!(report.is_complete());
+        // 之前的三个线程只剩下一个，其他的线程都是新建的
+        let ids: HashSet<usize> = rx.iter().collect();
+        assert!(ids.iter().filter(|&&id| id < 3).count() <= 1, "{:?}", ids);
+        assert!(report.joined.iter().filter(|&&id| id < 3).count() <= 1, "{:?}", report);
     }
-}
 
-impl ThreadPool {
-    ///创建线程池。
-    /// 
-    /// 线程池中线程的数量
-    /// 
-    /// # panic
-    /// 
-    /// `new` 函数会在 size 为 0 时触发 panic。
-    pub fn new(size: usize) -> ThreadPool {
-    // 负的线程数量没有任何意义，因此这里选择了 usize 作为 size 参数的类型
-        
-        // 使用 assert! 宏进行断言，当 size 为 0 时，触发程序的 panic
-        // 我们也可以在 new 函数中返回一个 Result 而不再使用 assert! 宏
-        assert!(size > 0);
+    #[test]
+    fn idle_workers_steal_jobs() {
+        let pool = Arc::new(ThreadPool::new(4));
+        let (tx, rx) = channel();
+        let (done_tx, done_rx) = channel();
 
-        // with_capacity 方法创建一个指定长度的数组
-        // with_capacity 与 Vec::new 有些类似，但区别在于 with_capacity 会为动态数组预分配出指定的空间
-        // 在知晓存储大小的前提下预先分配存储空 间要比使用 Vec::new 在插入时动态扩展大小更有效率一些
-        // let mut threads = Vec::with_capacity(size);
-        let mut workers = Vec::with_capacity(size);
-        
-        // 我们希望刚刚创建的Worker结构体能够从存储在ThreadPool的队列中获取需要执行的代码，并将它们发送到线程中运行
-        // 因此我们使用 channel 作为线程通信的方式
-        // 将我们需要线程执行的代码发送给线程，也就是 Worker 实例
-        // 创建一个 channel，并持有发送端 sender
-        let (sender, receiver) = mpsc::channel();
-//
-        // 为了在多个线程中共享所有权并允许线程修改共享值，我们可以使用 Arc<Mutex<T>>
-        // Arc 类型允许多个工作线程拥有同一个接收者，而 Mutex 则保证了一次只有一个工作线程能够从接收端得到任务
-        let receiver = Arc::new(Mutex::new(receiver));
+        // 线程池中的任务提交的新任务都放入同一个线程的队列，其他空闲的线程会把它们偷走
+        let inner = Arc::clone(&pool);
+        pool.execute(move || {
+            for _ in 0..32 {
+                let tx = tx.clone();
+                inner.execute(move || {
+                    thread::sleep(Duration::from_millis(2));
+                    tx.send(thread::current().id()).unwrap();
+                });
+            }
+            // 先释放线程池，避免最后一个引用在线程池自己的线程中被丢弃
+            drop(inner);
+            done_tx.send(()).unwrap();
+        });
 
+        done_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        let threads: HashSet<_> = rx.iter().take(32).collect();
+        assert!(threads.len() > 1);
+    }
 
-        for id in 0..size {
-            // 创建线程并将其存储到动态数组
+    #[test]
+    fn events_and_stats() {
+        let (tx, rx) = channel();
+        let pool = ThreadPool::builder()
+            .min_threads(1)
+            .max_threads(1)
+            .event_hook(move |event| tx.send(*event).unwrap())
+            .build();
 
-            // 生成的每个 Worker 实例都会持有 channel 的接收端 receiver
-            // 如果我们将 receiver 直接传递给多个 Worker 实例，那么编译会失败：
-            // error[E0382]: use of moved value: `receiver`
-            // 直接传递给线程，会发生所有权的转移，因此，多个线程无法直接使用 receiver
-            // 因为 Rust 提供的 channel 是多生产者、单消费者的，这也意味着你不能简单地通过克隆接收端来解决上述问题
-            // 我们希望所有的线程都使用这一个消费者从而能够在线程间分发任务
-            // workers.push(Worker::new(id, receiver))
+        let name = pool.spawn(|| thread::current().name().map(String::from)).join().unwrap();
+        assert_eq!(Some("pool-worker-0"), name.as_deref());
+        assert!(pool.spawn(|| thread::sleep(Duration::from_millis(10))).join().is_ok());
+        assert!(pool.spawn(|| panic!("counted")).join().is_err());
 
-            // 创建新的 Worker 时克隆Arc来增加引用计数
-            // 从而使所有的工作线程可以共享接收端的所有权
-            workers.push(Worker::new(id, Arc::clone(&receiver)));
+        // JobFinished 在 join 返回之后才发出，等它出现之后再读取统计数据
+        let events: Vec<Event> = rx.iter().take(7).collect();
+        assert_eq!(Event::WorkerStarted { worker: 0 }, events[0]);
+        assert!(matches!(events[6], Event::JobFinished { worker: 0, panicked: true, .. }));
+
+        let stats = pool.stats();
+        assert_eq!((1, 0, 0, 3, 1), (stats.threads, stats.queued, stats.active, stats.completed, stats.panicked));
+        assert!(stats.average_run >= Duration::from_millis(10) / 3);
+
+        pool.shutdown(ShutdownPolicy::Drain, Duration::from_secs(5));
+        let rest: Vec<Event> = rx.iter().collect();
+        assert_eq!(
+            vec![Event::ShutdownStarted { policy: ShutdownPolicy::Drain }, Event::WorkerTerminated { worker: 0 }],
+            rest
+        );
+    }
+
+    #[test]
+    fn high_priority_first() {
+        let pool = ThreadPool::new(1);
+        let (started_tx, started_rx) = channel();
+        let (release_tx, release_rx) = channel::<()>();
+        pool.execute(move || {
+            started_tx.send(()).unwrap();
+            release_rx.recv().unwrap();
+        });
+        started_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+
+        // 唯一的线程被占住时提交的任务，高优先级的任务先执行
+        let (tx, rx) = channel();
+        for (priority, name) in [(Priority::Normal, "a"), (Priority::Normal, "b"), (Priority::High, "urgent")] {
+            let tx = tx.clone();
+            pool.execute_with_priority(priority, move || tx.send(name).unwrap());
         }
+        drop(tx);
+        release_tx.send(()).unwrap();
+        drop(pool);
+        assert_eq!(vec!["urgent", "a", "b"], rx.iter().collect::<Vec<_>>());
+    }
 
-        ThreadPool {
-            // threads
-            workers,
-            sender
+    #[test]
+    fn scheduled_jobs() {
+        let pool = ThreadPool::new(2);
+        let (tx, rx) = channel();
+
+        let start = Instant::now();
+        let delayed_tx = tx.clone();
+        pool.schedule_after(Duration::from_millis(30), move || delayed_tx.send("delayed").unwrap());
+        let cancelled_tx = tx.clone();
+        pool.schedule_after(Duration::from_millis(10), move || cancelled_tx.send("cancelled").unwrap()).cancel();
+        assert_eq!("delayed", rx.recv_timeout(Duration::from_secs(5)).unwrap());
+        assert!(start.elapsed() >= Duration::from_millis(30));
+
+        let handle = pool.schedule_at_fixed_rate(Duration::from_millis(5), move || tx.send("tick").unwrap());
+        for _ in 0..3 {
+            assert_eq!("tick", rx.recv_timeout(Duration::from_secs(5)).unwrap());
         }
+        handle.cancel();
+        assert!(handle.is_cancelled());
+        // 被取消的延迟任务没有执行，线程池关闭之后所有的发送端都被丢弃，rx.iter() 会结束
+        let report = pool.shutdown(ShutdownPolicy::Drain, Duration::from_secs(5));
+        assert!(report.is_complete());
+        assert!(rx.iter().all(|message| message == "tick"));
 
+        // 周期任务 panic 之后不再执行
+        let pool = ThreadPool::new(1);
+        let handle = pool.schedule_at_fixed_rate(Duration::from_millis(1), || panic!("stop"));
+        let start = Instant::now();
+        while !handle.is_cancelled() && start.elapsed() < Duration::from_secs(5) {
+            thread::sleep(Duration::from_millis(1));
+        }
+        thread::sleep(Duration::from_millis(10));
+        assert_eq!(1, pool.panic_count());
+
+        // 队列已满时到期的任务不会让 shutdown 超过它的超时时间
+        let pool = ThreadPool::with_queue_capacity(1, 1);
+        let (release_tx, release_rx) = channel::<()>();
+        let (started_tx, started_rx) = channel();
+        pool.execute(move || {
+            started_tx.send(()).unwrap();
+            let _ = release_rx.recv_timeout(Duration::from_secs(5));
+        });
+        started_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        pool.execute(|| {});
+        pool.schedule_after(Duration::from_millis(1), || {});
+        thread::sleep(Duration::from_millis(20));
+        let start = Instant::now();
+        let report = pool.shutdown(ShutdownPolicy::Abort, Duration::from_millis(100));
+        assert!(start.elapsed() < Duration::from_secs(1), "{:?}", start.elapsed());
+        assert_eq!(vec![0], report.timed_out);
+        release_tx.send(()).unwrap();
     }
-}
 
-impl ThreadPool {
-    // 仿照 Thread::spawn 方法的签名构造 execute 方法
-    // pub fn spawn<F, T>(f: F) -> JoinHandle<T>
-    // where
-    // F: FnOnce() -> T + Send + 'static,
-    // T: Send + 'static
-    // 因为 execute 接收的闭包最终要传入 spawn 方法中
-    // 而请求处理的线程只会执行一次闭包，所以这里就是使用 FnOnce 这个 trait 约束闭包的类型
-    // 同时类型参数 F 还需要满足 Send trait 和 生命周期 'static
-    // 因为满足 Send trait 约束的闭包才可以从一个线程传递到另一个线程
-    // 而由于我们不知道线程究竟会执行多长时间，所以闭包必须是 'static 的
-    // FnOnce 后的 () 意味着传入的闭包既没有参数，也不返回结果
-    // 就像函数定义一样，我们可以省略签名中的返回值，但却不能省略函数名后的圆括号，即便括号中没有任何参数
-    pub fn execute<F>(&self, f: F) where F: FnOnce() + Send +'static {
+    #[test]
+    fn scoped_jobs_borrow() {
+        let pool = ThreadPool::new(2);
+        let data = [1, 2, 3, 4, 5, 6];
+        let mut sums = [0; 2];
+        pool.scope(|s| {
+            for (chunk, sum) in data.chunks(3).zip(sums.iter_mut()) {
+                s.execute(move || *sum = chunk.iter().sum());
+            }
+        });
+        assert_eq!([6, 15], sums);
 
-        // 接收到闭包后会创建出一个新的Job实例，使用 Box 指针包裹一个函数类型
-        let job = Box::new(f);
-        // 将这个任务传递给通道的发送端，为了应对发送失败的情形，我们在 send 后直接调用了 unwrap
-        // self.sender.send(job).unwrap();
+        // 唯一的线程在任务中等待内层的 scope，它会自己执行内层的任务
+        let pool = Arc::new(ThreadPool::new(1));
+        let inner = Arc::clone(&pool);
+        let total = pool
+            .spawn(move || {
+                let mut parts = [0; 4];
+                inner.scope(|s| {
+                    for (i, part) in parts.iter_mut().enumerate() {
+                        s.execute(move || *part = i * 10);
+                    }
+                });
+                drop(inner);
+                parts.iter().sum::<usize>()
+            })
+            .join()
+            .unwrap();
+        assert_eq!(60, total);
 
-        // 通过通道发送的不再是 job 实例
-        // 而是发送 Message 枚举的 NewJob 变体
-        self.sender.send(Message::NewJob(job)).unwrap();
+        // 队列容量为 1 时，唯一的线程提交作用域任务也不会阻塞在已满的队列上
+        let pool = Arc::new(ThreadPool::with_queue_capacity(1, 1));
+        let inner = Arc::clone(&pool);
+        let mut handle = pool.spawn(move || {
+            let mut parts = [0; 8];
+            inner.scope(|s| {
+                for (i, part) in parts.iter_mut().enumerate() {
+                    s.execute(move || *part = i);
+                }
+            });
+            let doubled = inner.par_map(0..100, |n| n * 2);
+            drop(inner);
+            parts.iter().sum::<usize>() + doubled.iter().sum::<usize>()
+        });
+        assert_eq!(28 + 9900, handle.join_timeout(Duration::from_secs(5)).unwrap().unwrap());
+
+        // 任务 panic 时，scope 在其他任务结束之后继续这个 panic
+        let finished = std::sync::atomic::AtomicUsize::new(0);
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            pool.scope(|s| {
+                s.execute(|| panic!("scoped"));
+                for _ in 0..4 {
+                    s.execute(|| {
+                        thread::sleep(Duration::from_millis(5));
+                        finished.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                    });
+                }
+            })
+        }));
+        assert_eq!("scoped", *result.unwrap_err().downcast::<&str>().unwrap());
+        assert_eq!(4, finished.into_inner());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn parallel_helpers() {
+        let pool = ThreadPool::new(3);
+        let doubled = pool.par_map(0..1000, |n| n * 2);
+        assert_eq!((0..1000).map(|n| n * 2).collect::<Vec<_>>(), doubled);
+        assert!(pool.par_map(Vec::<i32>::new(), |n| n).is_empty());
+
+        let total = std::sync::atomic::AtomicUsize::new(0);
+        pool.par_for_each(1..=100, |n| {
+            total.fetch_add(n, std::sync::atomic::Ordering::SeqCst);
+        });
+        assert_eq!(5050, total.into_inner());
+
+        let data: Vec<u32> = (1..=10).collect();
+        assert_eq!(vec![6, 15, 24, 10], pool.par_chunks(&data, 3, |chunk| chunk.iter().sum::<u32>()));
+
+        // 错误和 panic 都会回到调用者
+        let parsed = pool.try_par_map(["1", "2", "x", "4"], |s| s.parse::<i32>());
+        assert!(parsed.is_err());
+        assert_eq!(Ok(vec![1, 2]), pool.try_par_map(["1", "2"], |s| s.parse::<i32>()));
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            pool.par_map(0..10, |n| if n == 7 { panic!("item {}", n) } else { n })
+        }));
+        assert_eq!("item 7", *result.unwrap_err().downcast::<String>().unwrap());
+    }
+}
@@ -0,0 +1,754 @@
+// 工作窃取调度
+//
+// 之前所有线程共享一个 Arc<Mutex<mpsc::Receiver<Message>>>，每次取任务都要竞争同一把锁，负载高的时候取任务的过程实际上是串行的
+// 现在每个线程有一个自己的任务队列：
+// 1. 线程池外部提交的任务轮流放入各个队列，线程池中的任务提交的新任务放入当前线程自己的队列
+// 2. 线程先从自己队列的头部取任务，自己的队列空了，再从其他队列的尾部偷走一半的任务
+// 3. 所有队列都空了，线程才会在 Condvar 上休眠，提交任务时只有存在休眠的线程才需要获取全局的锁去唤醒它
+// 每个队列虽然也是一个 Mutex，但大多数时候只有队列的主人在使用它，几乎不会发生竞争
+//
+// 提交和执行一个任务时尽量不修改所有线程共享的计数器，否则这个计数器所在的缓存行会在所有 CPU 之间来回传递：
+// 1. 队列的长度和执行任务的统计数据都记录在各个队列自己的 Slot 中，需要总数时（例如 stats）再加起来
+// 2. 只有设置了队列容量时，才需要用一个共享的计数器来预留位置
+// 3. 外部提交的任务轮流放入各个队列时，使用的是提交任务的线程自己的计数器
+//
+// 线程的数量在 min_threads 和 max_threads 之间变化：
+// 1. 队列一共有 max_threads 个，每个线程占用其中一个，线程退出后队列留给之后新建的线程
+// 2. 排队的任务比空闲的线程多时，新建一个线程
+// 3. 线程空闲超过 keep_alive 并且线程数量多于 min_threads 时，线程退出
+// 没有线程占用的队列中的任务同样会被其他线程偷走
+//
+// 高优先级的任务放入所有线程共享的一个队列，线程取任务时总是先检查这个队列，然后才是自己的队列和偷任务
+// 延迟任务和周期任务由定时器线程在到期时放入队列，见 timer 模块
+
+use std::cell::Cell;
+use std::collections::VecDeque;
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex, RwLock, Weak};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::timer::{ScheduledHandle, Timer};
+use crate::{Event, Job, Panicked, Priority, Stats};
+
+thread_local! {
+    // 当前线程属于哪个线程池（Shared 的地址），它占用的队列的下标，以及线程的 id
+    static CURRENT: Cell<Option<(usize, usize, usize)>> = const { Cell::new(None) };
+    // 当前线程从外部提交的下一个任务放入哪个队列（对队列的数量取余）
+    static NEXT_SLOT: Cell<usize> = const { Cell::new(0) };
+}
+
+// 线程池和所有线程共享的状态
+pub(crate) struct Shared {
+    // 一共 max_threads 个队列
+    slots: Vec<Slot>,
+    // 高优先级的任务，以及其中任务的数量，数量为 0 时不用去获取这个所有线程共享的锁
+    high: Mutex<VecDeque<Queued>>,
+    high_queued: AtomicUsize,
+    // 设置了队列容量时，所有队列中任务的数量，包括已经预留了位置、正在放入队列的任务
+    reserved: AtomicUsize,
+    // reserved 的上限，None 表示不限制，这时不需要预留位置
+    capacity: Option<usize>,
+
+    min_threads: usize,
+    keep_alive: Duration,
+    // 现有的线程数量
+    live: AtomicUsize,
+    // 下一个新建的线程的 id，线程退出后 id 也不会被重复使用
+    next_id: AtomicUsize,
+    // 现有的线程，线程空闲退出时会把自己从这里移除
+    workers: Mutex<Vec<Worker>>,
+
+    // sleep_lock 保护下面两个 Condvar，真正的状态都在原子变量中
+    sleep_lock: Mutex<()>,
+    // 有新的任务或者线程池正在关闭
+    work_available: Condvar,
+    // 队列中空出了位置
+    space_available: Condvar,
+    // 正在休眠等待任务的线程数量
+    sleeping: AtomicUsize,
+    // 已经唤醒了一个线程，但它还没有醒来，这时不再唤醒其他线程，避免一次提交很多任务时所有线程同时醒来抢任务
+    // 醒来的线程取到任务之后，如果还有剩下的任务，会接着唤醒下一个线程
+    waking: AtomicBool,
+    // 因为队列已满而阻塞在 execute 中的调用者的数量
+    blocked: AtomicUsize,
+
+    // 线程池正在关闭，线程执行完队列中的任务之后退出
+    shutting_down: AtomicBool,
+    // 使用 ShutdownPolicy::Abort 关闭线程池之后，线程会丢弃取到的任务
+    aborted: AtomicBool,
+
+    // 任务 panic 的次数
+    panics: AtomicUsize,
+    // 任务 panic 时调用的回调，参数是执行任务的线程的 id 和 panic 的信息
+    panic_handler: RwLock<Option<PanicHandler>>,
+    // 接收事件的回调，没有设置回调时 hooked 是 false，不用每次都去获取读锁
+    event_hook: RwLock<Option<EventHook>>,
+    hooked: AtomicBool,
+
+    // 还没有到期的延迟任务和周期任务
+    timer: Timer,
+}
+
+pub(crate) type PanicHandler = Box<dyn Fn(usize, &Panicked) + Send + Sync>;
+pub(crate) type EventHook = Arc<dyn Fn(&Event) + Send + Sync>;
+
+// 一个队列，以及占用它的线程执行任务的统计数据
+// 对齐到 64 字节，不同线程的 Slot 不会落在同一个缓存行中，修改自己的计数器时不会影响其他线程
+#[repr(align(64))]
+struct Slot {
+    queue: Mutex<VecDeque<Queued>>,
+    // 队列中任务的数量，只在持有 queue 的锁时修改，检查有没有任务时不用获取锁
+    len: AtomicUsize,
+    // 是否已经被某个线程占用
+    occupied: AtomicBool,
+    // 下面的计数器只有占用这个 Slot 的线程会修改，其他线程只会读取，见 Slot::add
+    // 正在执行的任务数量，在任务中等待其他任务（例如 scope）时会帮忙执行任务，所以可能大于 1
+    busy: AtomicUsize,
+    // 执行结束的任务的数量，包括 panic 的任务
+    completed: AtomicUsize,
+    // 执行结束的任务在队列中等待的总时间和执行的总时间，单位是纳秒
+    total_wait: AtomicU64,
+    total_run: AtomicU64,
+}
+
+impl Slot {
+    fn new() -> Slot {
+        Slot {
+            queue: Mutex::new(VecDeque::new()),
+            len: AtomicUsize::new(0),
+            occupied: AtomicBool::new(false),
+            busy: AtomicUsize::new(0),
+            completed: AtomicUsize::new(0),
+            total_wait: AtomicU64::new(0),
+            total_run: AtomicU64::new(0),
+        }
+    }
+
+    fn push_back(&self, queued: Queued) {
+        let mut queue = self.queue.lock().unwrap();
+        queue.push_back(queued);
+        self.len.store(queue.len(), Ordering::SeqCst);
+    }
+
+    // 取出任务时 len 只会变小，其他线程晚一点看到也只是多检查一次队列，不需要 SeqCst
+    // 放入任务时必须是 SeqCst，和 wait_for_job 中先登记 sleeping 再检查 len 配合，不会错过唤醒
+    fn pop_front(&self) -> Option<Queued> {
+        let mut queue = self.queue.lock().unwrap();
+        let queued = queue.pop_front()?;
+        self.len.store(queue.len(), Ordering::Relaxed);
+        Some(queued)
+    }
+
+    // 只有一个线程修改的计数器，用普通的读取和写入代替原子的读-改-写（x86 上带 lock 前缀的指令），
+    // 每执行一个任务要修改好几次计数器，这样可以省下不少开销，其他线程读到的值只是稍微有些滞后
+    fn add(counter: &AtomicUsize, n: usize) {
+        counter.store(counter.load(Ordering::Relaxed).wrapping_add(n), Ordering::Relaxed);
+    }
+
+    fn add_u64(counter: &AtomicU64, n: u64) {
+        counter.store(counter.load(Ordering::Relaxed).wrapping_add(n), Ordering::Relaxed);
+    }
+}
+
+// 队列中的任务，记录放入队列的时间，用来计算等待的时间
+struct Queued {
+    job: Job,
+    enqueued: Instant,
+}
+
+// 线程池中的一个线程
+//
+// 为什么需要一个单独的 Worker 类型呢
+// 因为按照我们之前的设定，我们使用 thread::spawn 创建线程，spawn 方法接收一个闭包作为参数
+// spawn 方法在创建完线程以后会立即执行自己接收到的代码参数
+// 但是实际上，我们创建完线程以后，不需要立即执行，而是进入等待状态并执行随后传给它的代码
+
+// 因此我们需要创建 Worker 类，作为中间类型
+// Worker 类型持有线程（JoinHandle）
+
+// Worker 类型持有一个 id 和 thread
+// thread 属性用来持有 JoinHandle，也就是一个线程
+// 线程中等待并执行任务的循环现在是 Shared::work，关闭线程池时通过 Worker 找到并等待每个线程
+pub(crate) struct Worker {
+    pub(crate) id: usize,
+    // 之前 Drop 中只能可变借用 Worker，需要用 Option 的 take 方法把线程移出来
+    // 现在 take_workers 交出的是 Worker 的所有权，不再需要 Option
+    // thread: Option<thread::JoinHandle<()>>
+    pub(crate) thread: thread::JoinHandle<()>,
+}
+
+// wait_for_job 的结果
+enum Wait {
+    // 有新的任务
+    Found,
+    // 线程池正在关闭，并且没有剩下的任务
+    Shutdown,
+    // 空闲超过 keep_alive，线程可以退出
+    Idle,
+}
+
+impl Shared {
+    pub(crate) fn new(min_threads: usize, max_threads: usize, capacity: Option<usize>, keep_alive: Duration) -> Shared {
+        Shared {
+            slots: (0..max_threads).map(|_| Slot::new()).collect(),
+            high: Mutex::new(VecDeque::new()),
+            high_queued: AtomicUsize::new(0),
+            reserved: AtomicUsize::new(0),
+            // 容量为 0 时任何任务都放不进队列，按 1 处理
+            capacity: capacity.map(|capacity| capacity.max(1)),
+            min_threads,
+            keep_alive,
+            live: AtomicUsize::new(0),
+            next_id: AtomicUsize::new(0),
+            workers: Mutex::new(Vec::new()),
+            sleep_lock: Mutex::new(()),
+            work_available: Condvar::new(),
+            space_available: Condvar::new(),
+            sleeping: AtomicUsize::new(0),
+            waking: AtomicBool::new(false),
+            blocked: AtomicUsize::new(0),
+            shutting_down: AtomicBool::new(false),
+            aborted: AtomicBool::new(false),
+            panics: AtomicUsize::new(0),
+            panic_handler: RwLock::new(None),
+            event_hook: RwLock::new(None),
+            hooked: AtomicBool::new(false),
+            timer: Timer::new(),
+        }
+    }
+
+    pub(crate) fn is_shutting_down(&self) -> bool {
+        self.shutting_down.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn thread_count(&self) -> usize {
+        self.live.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn max_threads(&self) -> usize {
+        self.slots.len()
+    }
+
+    pub(crate) fn panic_count(&self) -> usize {
+        self.panics.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn set_panic_handler(&self, handler: PanicHandler) {
+        *self.panic_handler.write().unwrap() = Some(handler);
+    }
+
+    pub(crate) fn set_event_hook(&self, hook: EventHook) {
+        *self.event_hook.write().unwrap() = Some(hook);
+        self.hooked.store(true, Ordering::SeqCst);
+    }
+
+    // 调用事件回调，回调 panic 时不能影响线程池
+    pub(crate) fn emit(&self, event: Event) {
+        if !self.hooked.load(Ordering::Relaxed) {
+            return;
+        }
+        if let Some(hook) = &*self.event_hook.read().unwrap() {
+            let _ = panic::catch_unwind(AssertUnwindSafe(|| hook(&event)));
+        }
+    }
+
+    // 当前的统计数据，各项数据不是在同一时刻读取的，只是近似值
+    pub(crate) fn stats(&self) -> Stats {
+        let sum = |counter: fn(&Slot) -> u64| self.slots.iter().map(counter).sum::<u64>();
+        let completed = sum(|slot| slot.completed.load(Ordering::SeqCst) as u64);
+        let average = |total: u64| match completed {
+            0 => Duration::ZERO,
+            n => Duration::from_nanos(total / n),
+        };
+        Stats {
+            threads: self.live.load(Ordering::SeqCst),
+            queued: self.pending(),
+            active: self.busy(),
+            completed: completed as usize,
+            panicked: self.panics.load(Ordering::SeqCst),
+            average_wait: average(sum(|slot| slot.total_wait.load(Ordering::SeqCst))),
+            average_run: average(sum(|slot| slot.total_run.load(Ordering::SeqCst))),
+        }
+    }
+
+    // 所有队列中排队的任务数量
+    fn pending(&self) -> usize {
+        let normal: usize = self.slots.iter().map(|slot| slot.len.load(Ordering::SeqCst)).sum();
+        self.high_queued.load(Ordering::SeqCst) + normal
+    }
+
+    // 有没有排队的任务，不用获取任何锁
+    fn has_work(&self) -> bool {
+        self.high_queued.load(Ordering::SeqCst) > 0
+            || self.slots.iter().any(|slot| slot.len.load(Ordering::SeqCst) > 0)
+    }
+
+    // 正在执行任务的线程数量
+    fn busy(&self) -> usize {
+        self.slots.iter().map(|slot| slot.busy.load(Ordering::SeqCst)).sum()
+    }
+
+    // 在队列中预留一个位置，队列已满时返回 false，没有限制容量时总是成功
+    pub(crate) fn try_reserve(&self) -> bool {
+        let Some(capacity) = self.capacity else {
+            return true;
+        };
+        self.reserved
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| (n < capacity).then_some(n + 1))
+            .is_ok()
+    }
+
+    // 在队列中预留一个位置，队列已满时阻塞，直到某个线程取走一个任务
+    // 线程池的线程不能阻塞在这里：队列满了而所有线程都在等待时，就没有线程来取走任务了
+    // 所以线程池的任务提交新任务时，先在当前线程中执行排队的任务，直到空出位置
+    pub(crate) fn reserve(&self) {
+        if !self.in_worker() {
+            self.wait_for_space(false);
+            return;
+        }
+        while !self.try_reserve() {
+            if !self.run_pending_job() {
+                // 队列是满的，但是任务还没有真正放入队列，让出 CPU 等它放进去
+                thread::yield_now();
+            }
+        }
+    }
+
+    // 队列已满时阻塞，直到预留到位置，返回 true
+    // until_shutdown 为 true 时，线程池开始关闭之后放弃等待，返回 false
+    fn wait_for_space(&self, until_shutdown: bool) -> bool {
+        if self.try_reserve() {
+            return true;
+        }
+        let mut guard = self.sleep_lock.lock().unwrap();
+        // 先登记再检查，取走任务的线程要么能看到登记，要么这里能看到空出来的位置，不会错过唤醒
+        self.blocked.fetch_add(1, Ordering::SeqCst);
+        let reserved = loop {
+            if self.try_reserve() {
+                break true;
+            }
+            if until_shutdown && self.is_shutting_down() {
+                break false;
+            }
+            guard = self.space_available.wait(guard).unwrap();
+        };
+        self.blocked.fetch_sub(1, Ordering::SeqCst);
+        reserved
+    }
+
+    // 把任务放入队列，调用之前必须已经预留了位置
+    // 排队的任务比空闲的线程多时新建一个线程
+    pub(crate) fn push(self: &Arc<Self>, job: Job, priority: Priority) {
+        let queued = Queued { job, enqueued: Instant::now() };
+        match priority {
+            Priority::High => {
+                let mut high = self.high.lock().unwrap();
+                high.push_back(queued);
+                self.high_queued.fetch_add(1, Ordering::SeqCst);
+            }
+            Priority::Normal => {
+                let index = match CURRENT.get() {
+                    Some((pool, index, _)) if pool == self.address() => index,
+                    _ => {
+                        let next = NEXT_SLOT.get();
+                        NEXT_SLOT.set(next.wrapping_add(1));
+                        next % self.slots.len()
+                    }
+                };
+                self.slots[index].push_back(queued);
+            }
+        }
+        self.wake_one();
+        self.grow_if_backed_up();
+    }
+
+    // 有休眠的线程时唤醒其中一个
+    fn wake_one(&self) {
+        if self.sleeping.load(Ordering::SeqCst) == 0 || self.waking.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        let _guard = self.sleep_lock.lock().unwrap();
+        // 持有锁的时候，计入 sleeping 的线程一定在 wait 中，notify_one 一定能唤醒其中一个
+        // 检查之后所有线程都已经醒了，就没有线程会清除 waking，这里要自己清除
+        if self.sleeping.load(Ordering::SeqCst) > 0 {
+            self.work_available.notify_one();
+        } else {
+            self.waking.store(false, Ordering::SeqCst);
+        }
+    }
+
+    // 排队的任务比空闲的线程多时新建一个线程，线程数量已经达到 max_threads 时什么也不做
+    // 固定数量的线程池总是已经达到了 max_threads，不用再去统计排队的任务和空闲的线程
+    fn grow_if_backed_up(self: &Arc<Self>) {
+        let live = self.live.load(Ordering::SeqCst);
+        if live >= self.slots.len() {
+            return;
+        }
+        if self.pending() > live.saturating_sub(self.busy()) {
+            self.spawn_worker();
+        }
+    }
+
+    // 新建一个线程，线程数量已经达到 max_threads 或者线程池正在关闭时什么也不做
+    pub(crate) fn spawn_worker(self: &Arc<Self>) {
+        if self.is_shutting_down() {
+            return;
+        }
+        let max_threads = self.slots.len();
+        if self.live.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| (n < max_threads).then_some(n + 1)).is_err() {
+            return;
+        }
+        // 正在退出的线程已经减少了 live，但可能还没有让出它的队列，这时放弃这次新建
+        let Some(slot) = self.slots.iter().position(|slot| !slot.occupied.swap(true, Ordering::SeqCst)) else {
+            self.live.fetch_sub(1, Ordering::SeqCst);
+            return;
+        };
+
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        let shared = Arc::clone(self);
+        // 持有 workers 的锁直到新线程的 JoinHandle 放入 workers，新线程即使马上退出，也能找到并移除自己
+        let mut workers = self.workers.lock().unwrap();
+        // 线程池已经开始关闭并取走了所有线程，新线程不会再被等待，放弃这次新建
+        if self.is_shutting_down() {
+            self.slots[slot].occupied.store(false, Ordering::SeqCst);
+            self.live.fetch_sub(1, Ordering::SeqCst);
+            return;
+        }
+        // 线程的名字会出现在 top -H 和 panic 信息中
+        let thread = thread::Builder::new()
+            .name(format!("pool-worker-{}", id))
+            .spawn(move || shared.work(id, slot))
+            .expect("failed to spawn a worker thread");
+        workers.push(Worker { id, thread });
+    }
+
+    // 取出所有线程，用于关闭线程池
+    pub(crate) fn take_workers(&self) -> Vec<Worker> {
+        mem::take(&mut *self.workers.lock().unwrap())
+    }
+
+    // 每个线程执行的循环
+    fn work(self: Arc<Self>, id: usize, slot: usize) {
+        CURRENT.set(Some((self.address(), slot, id)));
+        self.emit(Event::WorkerStarted { worker: id });
+        loop {
+            // 之前每个线程都从同一个通道中接收任务：
+            // 首先调用了 receiver 的 lock 方法来请求互斥锁
+            // 并接着使用 unwrap 来处理可能出现的错误情形
+            // 请求获取锁的操作会在互斥体被污染时出错，而互斥体会在某个持有锁的线程崩溃而锁没有被正常释放时被污染
+            // 在这种情形下，调用 unwrap 触发当前线程的 panic 是非常恰当的行为
+            // 当然，你也可以将 unwrap 修改为 expect 来附带一个有意义的错误提示信息
+
+            // 在互斥体上得到锁以后，我们就可以通过调用 recv 来从通道中接收 Job 了
+            // 调用 recv 会阻塞当前线程，当通道中不存在任务时，当前线程就会一直处于等待状态。而 Mutex<T> 则保证了一次只有一个 Worker 线程尝试请求任务
+            // let job = receiver.lock().unwrap().recv().unwrap();
+
+            // 现在每个队列也是一个 Mutex，获取锁时同样使用 unwrap
+            // 但是取任务时不再阻塞，队列都空了才在 wait_for_job 中休眠，休眠时不持有任何队列的锁
+            //
+            // 自己的队列和其他线程的队列中都没有任务时休眠
+            let Some(queued) = self.find_job(slot) else {
+                match self.wait_for_job() {
+                    Wait::Found => continue,
+                    Wait::Shutdown => {
+                        self.emit(Event::WorkerTerminated { worker: id });
+                    }
+                    Wait::Idle => {
+                        self.retire(id, slot);
+                        self.emit(Event::WorkerRetired { worker: id, idle: self.keep_alive });
+                    }
+                }
+                break;
+            };
+            // 通过使用 loop 并在循环代码块内部而不是外部请求锁和任务， lock 方法中返回的 MutexGuard 会在 let job 语句结束后被立即丢弃
+            // 这确保了我们只会在调用 recv 的过程中持有锁，并能够在调用 job()之前将锁释放。因此，我们的服务器才可以同时响应多个请求
+            // 现在 find_job 中的 MutexGuard 同样在取出任务之后就被丢弃了，执行任务时不持有任何队列的锁，其他线程可以在这期间偷走队列中的任务
+            self.execute(id, slot, queued);
+        }
+    }
+
+    // 当前线程是不是这个线程池的线程
+    pub(crate) fn in_worker(&self) -> bool {
+        matches!(CURRENT.get(), Some((pool, _, _)) if pool == self.address())
+    }
+
+    // 线程池的线程需要等待其他任务时（例如 scope 等待其中的任务结束），不能只是阻塞，
+    // 否则所有线程都在等待时，它们等待的任务就永远没有线程来执行，所以先在当前线程中执行一个排队的任务
+    // 执行了一个任务时返回 true，当前线程不是这个线程池的线程或者没有排队的任务时返回 false
+    pub(crate) fn run_pending_job(&self) -> bool {
+        let Some((pool, slot, id)) = CURRENT.get() else {
+            return false;
+        };
+        if pool != self.address() {
+            return false;
+        }
+        let Some(queued) = self.find_job(slot) else {
+            return false;
+        };
+        self.execute(id, slot, queued);
+        true
+    }
+
+    // 执行一个从队列中取出的任务，并记录统计数据和事件
+    fn execute(&self, id: usize, slot: usize, Queued { job, enqueued }: Queued) {
+        // 使用 ShutdownPolicy::Abort 关闭线程池之后，取到的任务直接丢弃
+        if self.aborted.load(Ordering::SeqCst) {
+            return;
+        }
+
+        // 等待时间和执行时间共用开始执行的时间，每个任务只读取两次时钟
+        let start = Instant::now();
+        let wait = start.saturating_duration_since(enqueued);
+        self.emit(Event::JobStarted { worker: id, wait });
+        let slot = &self.slots[slot];
+        Slot::add(&slot.busy, 1);
+        // 任务 panic 时线程不会退出
+        let panicked = !self.run(id, job);
+        let run = start.elapsed();
+        Slot::add(&slot.busy, usize::MAX);
+
+        Slot::add_u64(&slot.total_wait, wait.as_nanos() as u64);
+        Slot::add_u64(&slot.total_run, run.as_nanos() as u64);
+        Slot::add(&slot.completed, 1);
+        self.emit(Event::JobFinished { worker: id, run, panicked });
+    }
+
+    // 空闲的线程退出：让出队列，并把自己从 workers 中移除
+    fn retire(self: &Arc<Self>, id: usize, slot: usize) {
+        CURRENT.set(None);
+        self.slots[slot].occupied.store(false, Ordering::SeqCst);
+        self.workers.lock().unwrap().retain(|worker| worker.id != id);
+        // 决定退出之后可能又有新的任务，提交任务的一方看到的还是退出之前的线程数量，没有新建线程，这里补上
+        self.grow_if_backed_up();
+    }
+
+    // 先取高优先级的任务，然后从自己的队列中取任务，自己的队列空了再去其他队列中偷
+    fn find_job(&self, slot: usize) -> Option<Queued> {
+        // 所有队列都是空的，不用再一个个加锁检查
+        if !self.has_work() {
+            return None;
+        }
+        let job = match self.pop_high() {
+            Some(job) => job,
+            None => {
+                // 单独的语句，pop_front 中的 MutexGuard 在语句结束时就被丢弃了，保证偷任务之前已经释放了自己队列的锁
+                // 如果写成 self.slots[slot].queue.lock().unwrap().pop_front().or_else(..)，临时的 MutexGuard 要到整个表达式结束才会被丢弃
+                // 这时两个线程互相偷对方的任务就会死锁
+                let own = self.slots[slot].pop_front();
+                own.or_else(|| self.steal(slot))?
+            }
+        };
+
+        // 还有剩下的任务并且有休眠的线程时，唤醒下一个线程来帮忙
+        if self.sleeping.load(Ordering::SeqCst) > 0 && self.has_work() {
+            self.wake_one();
+        }
+        // 设置了队列容量时，归还预留的位置，唤醒等待位置的调用者
+        if self.capacity.is_some() {
+            self.reserved.fetch_sub(1, Ordering::SeqCst);
+            if self.blocked.load(Ordering::SeqCst) > 0 {
+                let _guard = self.sleep_lock.lock().unwrap();
+                self.space_available.notify_one();
+            }
+        }
+        Some(job)
+    }
+
+    fn pop_high(&self) -> Option<Queued> {
+        if self.high_queued.load(Ordering::SeqCst) == 0 {
+            return None;
+        }
+        let mut high = self.high.lock().unwrap();
+        let job = high.pop_front()?;
+        self.high_queued.fetch_sub(1, Ordering::SeqCst);
+        Some(job)
+    }
+
+    // 从其他队列的尾部偷走一半的任务，返回其中一个，剩下的放入自己的队列
+    // 从下一个队列开始依次尝试，避免所有线程都去偷同一个队列
+    fn steal(&self, slot: usize) -> Option<Queued> {
+        let count = self.slots.len();
+        for offset in 1..count {
+            let victim = &self.slots[(slot + offset) % count];
+            // 没有任务的队列不用加锁
+            if victim.len.load(Ordering::SeqCst) == 0 {
+                continue;
+            }
+            let mut stolen = {
+                let mut queue = victim.queue.lock().unwrap();
+                let len = queue.len();
+                let stolen = queue.split_off(len / 2);
+                victim.len.store(queue.len(), Ordering::SeqCst);
+                stolen
+            };
+            let Some(job) = stolen.pop_front() else {
+                continue;
+            };
+            if !stolen.is_empty() {
+                let mut own = self.slots[slot].queue.lock().unwrap();
+                own.extend(stolen);
+                self.slots[slot].len.store(own.len(), Ordering::SeqCst);
+            }
+            return Some(job);
+        }
+        None
+    }
+
+    // 没有任务时休眠，直到有了新的任务、线程池关闭，或者空闲超过 keep_alive 并且可以减少线程
+    fn wait_for_job(&self) -> Wait {
+        let mut guard = self.sleep_lock.lock().unwrap();
+        // 和 reserve 一样，先登记再检查，提交任务的一方要么能看到登记，要么这里能看到新的任务
+        self.sleeping.fetch_add(1, Ordering::SeqCst);
+        // keep_alive 非常大（例如 Duration::MAX）时算不出 deadline，这时线程永远不会因为空闲而退出
+        let mut deadline = Instant::now().checked_add(self.keep_alive);
+        let wait = loop {
+            if self.has_work() {
+                break Wait::Found;
+            }
+            if self.is_shutting_down() {
+                break Wait::Shutdown;
+            }
+            guard = match deadline {
+                None => self.work_available.wait(guard).unwrap(),
+                Some(limit) => {
+                    let now = Instant::now();
+                    if now >= limit {
+                        if self.try_shrink() {
+                            break Wait::Idle;
+                        }
+                        // 线程数量已经是 min_threads，重新开始计时
+                        deadline = now.checked_add(self.keep_alive);
+                        continue;
+                    }
+                    self.work_available.wait_timeout(guard, limit - now).unwrap().0
+                }
+            };
+            self.waking.store(false, Ordering::SeqCst);
+        };
+        self.sleeping.fetch_sub(1, Ordering::SeqCst);
+        wait
+    }
+
+    // 线程数量多于 min_threads 时减少一个，返回是否减少了
+    fn try_shrink(&self) -> bool {
+        self.live
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| (n > self.min_threads).then(|| n - 1))
+            .is_ok()
+    }
+
+    // 登记一个在 due 到期的任务，到期时由定时器线程放入队列
+    pub(crate) fn schedule(self: &Arc<Self>, due: Instant, handle: ScheduledHandle, job: Job) {
+        let shared = Arc::clone(self);
+        self.timer.insert(due, handle, job, move || shared.run_timer());
+    }
+
+    // 登记一个周期任务，第一次在 period 之后执行
+    pub(crate) fn schedule_at_fixed_rate<F>(self: &Arc<Self>, period: Duration, f: F) -> ScheduledHandle
+    where
+        F: FnMut() + Send + 'static,
+    {
+        let handle = ScheduledHandle::new();
+        if let Some(due) = Instant::now().checked_add(period) {
+            let rate = FixedRate { shared: Arc::downgrade(self), period, due, handle: handle.clone(), f };
+            rate.arm();
+        }
+        handle
+    }
+
+    // 定时器线程执行的循环：把到期的任务放入队列，队列已满时和 execute 一样等待
+    // 线程池开始关闭之后不再等待，shutdown 等待定时器线程退出时不会被已满的队列卡住
+    fn run_timer(self: Arc<Self>) {
+        while let Some((job, handle)) = self.timer.next_due() {
+            if handle.is_cancelled() {
+                continue;
+            }
+            if !self.wait_for_space(true) {
+                break;
+            }
+            self.push(job, Priority::Normal);
+        }
+    }
+
+    // 停止定时器并等待定时器线程退出，还没有到期的任务都被丢弃，之后登记的任务也会被直接丢弃
+    // 必须在 begin_shutdown 之后调用，否则定时器线程可能一直阻塞在已满的队列上
+    pub(crate) fn stop_timer(&self) {
+        self.timer.stop();
+    }
+
+    // 通知所有线程执行完剩下的任务之后退出，abort 时剩下的任务会被丢弃
+    pub(crate) fn begin_shutdown(&self, abort: bool) {
+        if abort {
+            self.aborted.store(true, Ordering::SeqCst);
+        }
+        let _guard = self.sleep_lock.lock().unwrap();
+        self.shutting_down.store(true, Ordering::SeqCst);
+        self.work_available.notify_all();
+        // 唤醒等待队列空出位置的定时器线程，让它放弃等待
+        self.space_available.notify_all();
+    }
+
+    // 在 catch_unwind 中执行任务，任务 panic 时只结束这个任务，线程会继续处理之后的任务
+    // 否则 panic 会结束整个线程，线程池中的线程越来越少，Drop 中的 join 也会返回错误
+    // 任务正常结束时返回 true
+    fn run(&self, id: usize, job: Job) -> bool {
+        let Err(payload) = panic::catch_unwind(AssertUnwindSafe(job)) else {
+            return true;
+        };
+        self.panics.fetch_add(1, Ordering::SeqCst);
+        let panicked = Panicked::from_payload(payload);
+        if let Some(handler) = &*self.panic_handler.read().unwrap() {
+            // 回调本身 panic 时同样不能结束线程
+            let _ = panic::catch_unwind(AssertUnwindSafe(|| handler(id, &panicked)));
+        }
+        false
+    }
+
+    // 用地址区分不同的线程池
+    fn address(&self) -> usize {
+        self as *const Shared as usize
+    }
+}
+
+// 周期任务，每次执行结束之后登记下一次执行
+// 持有的是 Weak，登记在定时器中的周期任务不会让线程池一直存在
+struct FixedRate<F> {
+    shared: Weak<Shared>,
+    period: Duration,
+    // 下一次执行的时间
+    due: Instant,
+    handle: ScheduledHandle,
+    f: F,
+}
+
+impl<F> FixedRate<F>
+where
+    F: FnMut() + Send + 'static,
+{
+    fn arm(self) {
+        if let Some(shared) = self.shared.upgrade() {
+            let (due, handle) = (self.due, self.handle.clone());
+            shared.schedule(due, handle, Box::new(move || self.fire()));
+        }
+    }
+
+    fn fire(mut self) {
+        if self.handle.is_cancelled() {
+            return;
+        }
+        if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(&mut self.f)) {
+            // panic 之后不再执行，继续 panic，线程池会像普通任务一样记录这次 panic
+            self.handle.cancel();
+            panic::resume_unwind(payload);
+        }
+        // 按照上一次计划的时间计算下一次，执行时间不会让周期慢慢漂移
+        // 已经错过了下一次的时间时立即执行，但不会为了补上错过的次数而连续执行很多次
+        let Some(due) = self.due.checked_add(self.period) else {
+            return;
+        };
+        self.due = due.max(Instant::now());
+        self.arm();
+    }
+}
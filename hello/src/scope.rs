@@ -0,0 +1,144 @@
+// 作用域任务
+//
+// execute 要求闭包是 'static 的，因为线程池不知道任务什么时候执行，任务借用的数据可能在那之前就已经被释放了
+// scope 在返回之前会等待其中提交的所有任务结束，所以任务可以借用 scope 外面的局部变量，和 std::thread::scope 一样，
+// 只是任务由线程池现有的线程执行，而不是每个任务新建一个线程
+//
+// 线程池的队列只能存放 'static 的 Job，这里把任务的生命周期擦除之后放入队列，安全性完全依赖于 scope 的等待：
+// 1. 每个任务在执行结束或者被丢弃时（例如线程池使用 ShutdownPolicy::Abort 关闭）才会减少计数，
+//    并且是在任务本身和它借用的数据都已经被丢弃之后
+// 2. 传给 scope 的闭包 panic 时，同样要先等所有任务结束，然后再继续 panic
+
+use std::marker::PhantomData;
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+
+use crate::scheduler::Shared;
+use crate::{Job, Panicked, Priority};
+
+// 线程池的线程在 scope 中等待时，每隔多久检查一次队列中有没有可以帮忙执行的任务
+const HELP_POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// `ThreadPool::scope` 中用来提交任务的作用域
+///
+/// 通过它提交的任务可以借用生命周期为 `'env` 的数据，`scope` 返回之前这些任务都已经结束了
+pub struct Scope<'scope, 'env: 'scope> {
+    shared: Arc<Shared>,
+    state: Arc<State>,
+    // 和 std::thread::Scope 一样，让 'scope 和 'env 都是不变的
+    scope: PhantomData<&'scope mut &'scope ()>,
+    env: PhantomData<&'env mut &'env ()>,
+}
+
+// 所有任务共享的状态
+struct State {
+    // 还没有结束的任务的数量
+    pending: Mutex<usize>,
+    // pending 变为 0
+    finished: Condvar,
+    // 第一个 panic 的任务的 panic 信息
+    panicked: Mutex<Option<Panicked>>,
+}
+
+impl<'scope, 'env> Scope<'scope, 'env> {
+    /// 提交一个可以借用作用域外面的数据的任务
+    ///
+    /// 任务和 `execute` 提交的任务一样排队，队列已满时阻塞
+    /// 任务 panic 时，`scope` 会在所有任务结束之后继续这个 panic
+    pub fn execute<F>(&'scope self, f: F)
+    where
+        F: FnOnce() + Send + 'scope,
+    {
+        *self.state.pending.lock().unwrap() += 1;
+        let job = ScopedJob { f, done: Done(Arc::clone(&self.state)) };
+        let job: Box<dyn FnOnce() + Send + 'scope> = Box::new(move || job.run());
+        // SAFETY: scope 在所有任务结束或者被丢弃之前不会返回，任务借用的数据在这之前一直有效
+        let job: Job = unsafe { mem::transmute::<Box<dyn FnOnce() + Send + 'scope>, Job>(job) };
+        // 在线程池的任务中调用时，队列满了也不会阻塞，见 Shared::reserve
+        self.shared.reserve();
+        self.shared.push(job, Priority::Normal);
+    }
+
+    // 等待所有任务结束
+    // 当前线程是线程池的线程时，一边等待一边执行队列中的任务，否则所有线程都在等待时就没有线程来执行这些任务了
+    fn wait(&self) {
+        let helping = self.shared.in_worker();
+        loop {
+            let pending = self.state.pending.lock().unwrap();
+            if *pending == 0 {
+                return;
+            }
+            if !helping {
+                drop(self.state.finished.wait(pending).unwrap());
+                continue;
+            }
+            drop(pending);
+            if !self.shared.run_pending_job() {
+                // 队列中暂时没有任务，等待任务结束，或者过一会再检查队列
+                let pending = self.state.pending.lock().unwrap();
+                if *pending > 0 {
+                    drop(self.state.finished.wait_timeout(pending, HELP_POLL_INTERVAL).unwrap());
+                }
+            }
+        }
+    }
+}
+
+// 字段按声明的顺序丢弃：任务没有执行就被丢弃时，先丢弃闭包和它借用的数据，再减少计数
+struct ScopedJob<F> {
+    f: F,
+    done: Done,
+}
+
+impl<F: FnOnce()> ScopedJob<F> {
+    fn run(self) {
+        let ScopedJob { f, done } = self;
+        if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(f)) {
+            let panicked = Panicked::from_payload(payload);
+            let message = panicked.message().to_string();
+            done.0.panicked.lock().unwrap().get_or_insert(panicked);
+            drop(done);
+            // 和 spawn 的任务一样，带着 panic 信息继续 panic，线程池才能记录这次 panic
+            panic::resume_unwind(Box::new(message));
+        }
+    }
+}
+
+// 被丢弃时减少计数，最后一个任务结束时唤醒等待的 scope
+struct Done(Arc<State>);
+
+impl Drop for Done {
+    fn drop(&mut self) {
+        let mut pending = self.0.pending.lock().unwrap();
+        *pending -= 1;
+        if *pending == 0 {
+            self.0.finished.notify_all();
+        }
+    }
+}
+
+pub(crate) fn scope<'env, F, R>(shared: &Arc<Shared>, f: F) -> R
+where
+    F: for<'scope> FnOnce(&'scope Scope<'scope, 'env>) -> R,
+{
+    let scope = Scope {
+        shared: Arc::clone(shared),
+        state: Arc::new(State { pending: Mutex::new(0), finished: Condvar::new(), panicked: Mutex::new(None) }),
+        scope: PhantomData,
+        env: PhantomData,
+    };
+    let result = panic::catch_unwind(AssertUnwindSafe(|| f(&scope)));
+    scope.wait();
+
+    let result = match result {
+        Ok(result) => result,
+        Err(payload) => panic::resume_unwind(payload),
+    };
+    if let Some(panicked) = scope.state.panicked.lock().unwrap().take() {
+        // 从 payload 中取出的 Panicked 一定带着 payload
+        panic::resume_unwind(panicked.into_payload().unwrap());
+    }
+    result
+}
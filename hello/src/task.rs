@@ -0,0 +1,132 @@
+// spawn 提交的任务有返回值，返回值通过每个任务单独的通道送回给 TaskHandle
+// 任务在 catch_unwind 中执行，任务 panic 时调用者会从 join 得到 Panicked 错误，而不是让 panic 悄悄地消失在工作线程中
+
+use std::any::Any;
+use std::error::Error;
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{self, RecvTimeoutError, TryRecvError};
+use std::time::Duration;
+
+use crate::Job;
+
+// 任务没有正常返回：任务执行时 panic 了，或者任务在执行之前就被丢弃了（例如线程池已经关闭）
+pub struct Panicked {
+    message: String,
+    payload: Option<Box<dyn Any + Send>>,
+}
+
+impl Panicked {
+    pub(crate) fn from_payload(payload: Box<dyn Any + Send>) -> Panicked {
+        // panic! 的参数是字面量时 payload 是 &str，带格式化参数时是 String
+        let message = if let Some(s) = payload.downcast_ref::<&str>() {
+            s.to_string()
+        } else if let Some(s) = payload.downcast_ref::<String>() {
+            s.clone()
+        } else {
+            String::from("Box<dyn Any>")
+        };
+        Panicked { message, payload: Some(payload) }
+    }
+
+    fn dropped() -> Panicked {
+        Panicked { message: String::from("task was dropped before it finished"), payload: None }
+    }
+
+    // panic 时的信息
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    // 取出原始的 panic payload，可以交给 panic::resume_unwind 在当前线程中继续 panic
+    // 任务没有执行就被丢弃时返回 None
+    pub fn into_payload(self) -> Option<Box<dyn Any + Send>> {
+        self.payload
+    }
+}
+
+impl fmt::Debug for Panicked {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Panicked").field("message", &self.message).finish()
+    }
+}
+
+impl fmt::Display for Panicked {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "task panicked: {}", self.message)
+    }
+}
+
+impl Error for Panicked {}
+
+// spawn 返回的句柄，用来等待任务的返回值
+pub struct TaskHandle<T> {
+    receiver: mpsc::Receiver<Result<T, Panicked>>,
+    // try_join 或 join_timeout 已经取走了结果
+    finished: bool,
+}
+
+impl<T> TaskHandle<T> {
+    // 阻塞直到任务结束
+    pub fn join(self) -> Result<T, Panicked> {
+        if self.finished {
+            return Err(Panicked::dropped());
+        }
+        self.receiver.recv().unwrap_or_else(|_| Err(Panicked::dropped()))
+    }
+
+    // 不阻塞：任务还没有结束时返回 None
+    // 返回 Some 之后结果已经被取走，之后再调用都会返回 None
+    pub fn try_join(&mut self) -> Option<Result<T, Panicked>> {
+        if self.finished {
+            return None;
+        }
+        let result = match self.receiver.try_recv() {
+            Ok(result) => result,
+            Err(TryRecvError::Empty) => return None,
+            Err(TryRecvError::Disconnected) => Err(Panicked::dropped()),
+        };
+        self.finished = true;
+        Some(result)
+    }
+
+    // 最多等待 timeout，超时返回 None，之后还可以继续等待
+    pub fn join_timeout(&mut self, timeout: Duration) -> Option<Result<T, Panicked>> {
+        if self.finished {
+            return None;
+        }
+        let result = match self.receiver.recv_timeout(timeout) {
+            Ok(result) => result,
+            Err(RecvTimeoutError::Timeout) => return None,
+            Err(RecvTimeoutError::Disconnected) => Err(Panicked::dropped()),
+        };
+        self.finished = true;
+        Some(result)
+    }
+}
+
+// 把有返回值的闭包包装成普通的 Job，Job 执行结束后把结果发送给句柄
+pub(crate) fn task<F, T>(f: F) -> (Job, TaskHandle<T>)
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let (sender, receiver) = mpsc::channel();
+    let job = Box::new(move || {
+        // 调用者可能已经丢弃了句柄，这时结果没有人需要，忽略发送错误
+        match panic::catch_unwind(AssertUnwindSafe(f)) {
+            Ok(value) => {
+                let _ = sender.send(Ok(value));
+            }
+            Err(payload) => {
+                let panicked = Panicked::from_payload(payload);
+                let message = panicked.message.clone();
+                let _ = sender.send(Err(panicked));
+                // 原来的 payload 已经交给了句柄，带着 panic 信息继续 panic，线程池才能记录这次 panic
+                // resume_unwind 不会调用 panic hook，所以 panic 信息不会被打印两次
+                panic::resume_unwind(Box::new(message));
+            }
+        }
+    });
+    (job, TaskHandle { receiver, finished: false })
+}
@@ -0,0 +1,119 @@
+// 压缩文件和归档文件
+// 1. gzip：根据扩展名（.gz、.tgz）或者文件开头的魔数 1f 8b 识别，读取时透明地解压
+// 2. tar：根据扩展名（.tar、.tar.gz、.tgz）或者 512 字节文件头中的 ustar 标记识别，归档中的每个文件分别搜索
+
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+
+use flate2::bufread::MultiGzDecoder;
+use tar::Archive;
+
+// gzip 文件开头的两个字节
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+// tar 文件头中 ustar 标记的位置
+const TAR_MAGIC_OFFSET: usize = 257;
+const TAR_MAGIC: &[u8] = b"ustar";
+
+// 打开之后的输入：普通的文本，或者需要逐个搜索其中文件的 tar 归档
+pub enum Source {
+    Text(Box<dyn BufRead>),
+    Tar(Archive<Box<dyn BufRead>>),
+}
+
+// 根据文件名和内容判断输入的类型，path 为 None 表示标准输入，只能通过魔数判断
+pub fn detect(mut reader: Box<dyn BufRead>, path: Option<&Path>) -> io::Result<Source> {
+    let name = lowercase_name(path);
+
+    // fill_buf 只是查看缓冲区中的数据，并不会消费它们，之后读取时依然从文件开头开始
+    if is_gzip(&name, reader.fill_buf()?) {
+        // MultiGzDecoder 可以处理由多个 gzip 成员拼接而成的文件，例如 cat a.gz b.gz > c.gz
+        reader = Box::new(BufReader::new(MultiGzDecoder::new(reader)));
+    }
+
+    Ok(if is_tar(&name, reader.fill_buf()?) {
+        Source::Tar(Archive::new(reader))
+    } else {
+        Source::Text(reader)
+    })
+}
+
+// 根据文件名和文件开头的内容判断是否是 gzip 压缩的文件，name 是小写的文件名
+pub fn is_gzip(name: &str, head: &[u8]) -> bool {
+    name.ends_with(".gz") || name.ends_with(".tgz") || head.starts_with(&GZIP_MAGIC)
+}
+
+// 根据文件名和文件开头的内容（已经解压）判断是否是 tar 归档
+pub fn is_tar(name: &str, head: &[u8]) -> bool {
+    name.ends_with(".tar") || name.ends_with(".tar.gz") || name.ends_with(".tgz")
+        || (head.len() >= TAR_MAGIC_OFFSET + TAR_MAGIC.len()
+            && &head[TAR_MAGIC_OFFSET..TAR_MAGIC_OFFSET + TAR_MAGIC.len()] == TAR_MAGIC)
+}
+
+// 小写的文件名，标准输入没有文件名，返回空字符串
+pub fn lowercase_name(path: Option<&Path>) -> String {
+    path.and_then(|p| p.file_name())
+        .map(|n| n.to_string_lossy().to_lowercase())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(vec![], Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn tarball(files: &[(&str, &str)]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(vec![]);
+        for (name, content) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append_data(&mut header, name, content.as_bytes()).unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    fn text(source: Source) -> String {
+        match source {
+            Source::Text(mut reader) => {
+                let mut s = String::new();
+                reader.read_to_string(&mut s).unwrap();
+                s
+            }
+            Source::Tar(_) => panic!("expected text"),
+        }
+    }
+
+    #[test]
+    fn gzip_by_magic_bytes() {
+        let reader: Box<dyn BufRead> = Box::new(io::Cursor::new(gzip(b"a frog\n")));
+        assert_eq!("a frog\n", text(detect(reader, None).unwrap()));
+
+        let reader: Box<dyn BufRead> = Box::new(io::Cursor::new(b"plain\n".to_vec()));
+        assert_eq!("plain\n", text(detect(reader, Some(Path::new("poem.txt"))).unwrap()));
+    }
+
+    #[test]
+    fn tar_inside_gzip() {
+        let data = gzip(&tarball(&[("docs/poem.txt", "a frog\n"), ("notes.txt", "a bog\n")]));
+        let reader: Box<dyn BufRead> = Box::new(io::Cursor::new(data));
+
+        let mut archive = match detect(reader, Some(Path::new("logs.tgz"))).unwrap() {
+            Source::Tar(archive) => archive,
+            Source::Text(_) => panic!("expected tar"),
+        };
+        let names: Vec<String> = archive.entries().unwrap()
+            .map(|entry| entry.unwrap().path().unwrap().display().to_string())
+            .collect();
+        assert_eq!(vec!["docs/poem.txt", "notes.txt"], names);
+    }
+}
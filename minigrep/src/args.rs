@@ -0,0 +1,486 @@
+// 命令行参数解析
+// 支持的写法和 grep 类似：
+// 1. 短选项可以合并书写，例如 -in 等价于 -i -n
+// 2. 带值的选项既可以写成 -e PATTERN，也可以写成 -ePATTERN；长选项可以写成 --include GLOB 或者 --include=GLOB
+// 3. -- 之后的所有参数都当作普通参数，即使它们以 - 开头，例如 minigrep -- -foo poem.txt
+
+use std::error::Error;
+use std::fmt;
+use std::fs;
+
+use crate::case::CaseMode;
+use crate::matcher::Boundary;
+use crate::printer::ColorChoice;
+use crate::Config;
+
+pub const USAGE: &str = "\
+Usage: minigrep [OPTIONS] PATTERN FILE...
+       minigrep [OPTIONS] -e PATTERN... FILE...
+
+Options:
+  -e, --regexp PATTERN    use PATTERN for matching, can be given multiple times
+  -f, --file FILE         read patterns from FILE, one per line
+  -E, --regex             treat patterns as regular expressions
+  -F, --fixed-strings     treat patterns as literal strings (default)
+  -w, --word-regexp       only match whole words
+  -x, --line-regexp       only match whole lines
+  -U, --multiline         let patterns match across line breaks
+  -i, --ignore-case       ignore case distinctions (overrides CASE_INSENSITIVE);
+                          with -E, only single characters are folded, so STRASSE
+                          matches Straße without -E but not with it
+  -s, --case-sensitive    match case exactly (overrides CASE_INSENSITIVE)
+  -S, --smart-case        ignore case unless the pattern contains uppercase letters
+  -n, --line-number       prefix each line of output with its line number
+  -b, --byte-offset       prefix each line of output with its byte offset
+  -A, --after-context NUM print NUM lines of trailing context
+  -B, --before-context NUM
+                          print NUM lines of leading context
+  -C, --context NUM       print NUM lines of leading and trailing context
+  -c, --count             print only a count of matching lines
+  -v, --invert-match      select non-matching lines
+      --with-pattern      prefix each matching line with the patterns it hit
+  -l, --files-with-matches
+                          print only the names of files containing matches
+  -m, --max-count NUM     stop reading a file after NUM selected lines
+      --color[=WHEN]      highlight matches; WHEN is auto (default), always or never
+      --json              print one JSON object per match
+  -r, --replace TEXT      print matching lines with each match replaced by TEXT;
+                          with -E, TEXT may refer to groups as $1 or ${name}
+      --in-place          with --replace, rewrite the files instead of printing
+  -j, --threads NUM       search files with NUM threads (default: number of CPUs)
+      --include GLOB      only search files matching GLOB when FILE is a directory
+      --exclude GLOB      skip files and directories matching GLOB
+      --no-ignore         don't respect .gitignore and .ignore files, and search target/
+  -a, --text              search binary files as if they were text
+  -h, --help              print this help message
+  --                      treat all following arguments as positional";
+
+// 参数解析过程中可能出现的错误
+// 使用枚举代替之前的 &'static str，调用者可以根据不同的变体做不同的处理
+// 例如 Help 并不是真正的错误，main 函数遇到它时打印帮助信息并正常退出
+#[derive(Debug, PartialEq)]
+pub enum ArgsError {
+    // 用户请求打印帮助信息
+    Help,
+    // 不认识的选项
+    UnknownFlag(String),
+    // 选项需要一个值，但是没有提供
+    MissingValue(String),
+    // 选项的值不合法，例如 -A 后面不是数字：(选项, 值)
+    InvalidValue(String, String),
+    // 不接收值的选项却通过 --flag=value 的形式提供了值
+    UnexpectedValue(String),
+    // 既没有 -e，也没有位置参数作为搜索模式
+    MissingPattern,
+    // -f 指定的模式文件无法读取：(文件名, 错误信息)
+    PatternFile(String, String),
+    MissingFilename,
+    // 两个选项不能同时使用，例如 -U 和 -v
+    ConflictingFlags(String, String),
+    // 选项只能和另一个选项一起使用，例如 --in-place 需要 --replace：(选项, 需要的选项)
+    RequiresFlag(String, String),
+}
+
+impl fmt::Display for ArgsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ArgsError::Help => write!(f, "help requested"),
+            ArgsError::UnknownFlag(flag) => write!(f, "unknown option '{}'", flag),
+            ArgsError::MissingValue(flag) => write!(f, "option '{}' requires a value", flag),
+            ArgsError::InvalidValue(flag, value) => write!(f, "invalid value '{}' for option '{}'", value, flag),
+            ArgsError::UnexpectedValue(flag) => write!(f, "option '{}' doesn't take a value", flag),
+            ArgsError::MissingPattern => write!(f, "Didn't get a query string"),
+            ArgsError::PatternFile(path, e) => write!(f, "can't read pattern file '{}': {}", path, e),
+            ArgsError::MissingFilename => write!(f, "Didn't get a file name"),
+            ArgsError::ConflictingFlags(a, b) => write!(f, "options '{}' and '{}' can't be used together", a, b),
+            ArgsError::RequiresFlag(a, b) => write!(f, "option '{}' requires '{}'", a, b),
+        }
+    }
+}
+
+impl Error for ArgsError {}
+
+// 解析命令行参数（不包含程序名）
+// env_case_insensitive 表示是否设置了 CASE_INSENSITIVE 环境变量
+// 命令行参数的优先级高于环境变量：只有命令行没有指定 -i 或 -s 时，才使用环境变量的设置
+pub fn parse<I>(args: I, env_case_insensitive: bool) -> Result<Config, ArgsError>
+where
+    I: IntoIterator<Item = String>,
+{
+    let mut args = args.into_iter();
+
+    let mut patterns = vec![];
+    let mut positional = vec![];
+    // None 表示命令行没有指定大小写选项，-i / -s / -S 以最后出现的一个为准
+    let mut case_mode = None;
+    // -E / -F 以最后出现的一个为准
+    let mut regex = false;
+    // -w / -x 同样以最后出现的一个为准
+    let mut boundary = Boundary::None;
+    let mut multiline = false;
+    let mut line_number = false;
+    let mut byte_offset = false;
+    let mut before_context = 0;
+    let mut after_context = 0;
+    let mut count = false;
+    let mut invert = false;
+    let mut with_pattern = false;
+    let mut pattern_file = false;
+    let mut files_with_matches = false;
+    let mut max_count = None;
+    let mut threads = 0;
+    let mut color = ColorChoice::Auto;
+    let mut json = false;
+    let mut replace = None;
+    let mut in_place = false;
+    let mut no_ignore = false;
+    let mut text = false;
+    let mut include = vec![];
+    let mut exclude = vec![];
+
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            positional.extend(args.by_ref());
+            break;
+        }
+
+        if let Some(long) = arg.strip_prefix("--") {
+            // 长选项的值可以通过 = 直接给出
+            let (name, inline) = match long.split_once('=') {
+                Some((name, value)) => (name, Some(value.to_string())),
+                None => (long, None),
+            };
+            let flag = format!("--{}", name);
+
+            match name {
+                "regexp" => patterns.push(take_value(&flag, inline, &mut args)?),
+                "file" => {
+                    patterns.extend(read_patterns(&take_value(&flag, inline, &mut args)?)?);
+                    pattern_file = true;
+                }
+                "include" => include.push(take_value(&flag, inline, &mut args)?),
+                "exclude" => exclude.push(take_value(&flag, inline, &mut args)?),
+                "after-context" => after_context = take_number(&flag, inline, &mut args)?,
+                "before-context" => before_context = take_number(&flag, inline, &mut args)?,
+                "max-count" => max_count = Some(take_number(&flag, inline, &mut args)?),
+                "threads" => threads = take_number(&flag, inline, &mut args)?,
+                "replace" => replace = Some(take_value(&flag, inline, &mut args)?),
+                // 和 grep 一样，单独的 --color 等价于 --color=auto，不会读取下一个参数
+                "color" | "colour" => {
+                    color = match inline.as_deref() {
+                        None | Some("auto") => ColorChoice::Auto,
+                        Some("always") => ColorChoice::Always,
+                        Some("never") => ColorChoice::Never,
+                        Some(v) => return Err(ArgsError::InvalidValue(flag, v.to_string())),
+                    }
+                }
+                "context" => {
+                    after_context = take_number(&flag, inline, &mut args)?;
+                    before_context = after_context;
+                }
+                _ => {
+                    if inline.is_some() {
+                        return Err(ArgsError::UnexpectedValue(flag));
+                    }
+                    match name {
+                        "help" => return Err(ArgsError::Help),
+                        "regex" => regex = true,
+                        "fixed-strings" => regex = false,
+                        "word-regexp" => boundary = Boundary::Word,
+                        "line-regexp" => boundary = Boundary::Line,
+                        "multiline" => multiline = true,
+                        "ignore-case" => case_mode = Some(CaseMode::Insensitive),
+                        "case-sensitive" => case_mode = Some(CaseMode::Sensitive),
+                        "smart-case" => case_mode = Some(CaseMode::Smart),
+                        "line-number" => line_number = true,
+                        "byte-offset" => byte_offset = true,
+                        "count" => count = true,
+                        "invert-match" => invert = true,
+                        "files-with-matches" => files_with_matches = true,
+                        "with-pattern" => with_pattern = true,
+                        "json" => json = true,
+                        "in-place" => in_place = true,
+                        "no-ignore" => no_ignore = true,
+                        "text" => text = true,
+                        _ => return Err(ArgsError::UnknownFlag(flag)),
+                    }
+                }
+            }
+        } else if arg.len() > 1 && arg.starts_with('-') {
+            // 短选项，可能是多个选项合并在一起，例如 -inv
+            for (i, c) in arg.char_indices().skip(1) {
+                match c {
+                    'h' => return Err(ArgsError::Help),
+                    'E' => regex = true,
+                    'F' => regex = false,
+                    'w' => boundary = Boundary::Word,
+                    'x' => boundary = Boundary::Line,
+                    'U' => multiline = true,
+                    'a' => text = true,
+                    'i' => case_mode = Some(CaseMode::Insensitive),
+                    's' => case_mode = Some(CaseMode::Sensitive),
+                    'S' => case_mode = Some(CaseMode::Smart),
+                    'n' => line_number = true,
+                    'b' => byte_offset = true,
+                    'c' => count = true,
+                    'v' => invert = true,
+                    'l' => files_with_matches = true,
+                    'e' | 'f' | 'A' | 'B' | 'C' | 'j' | 'm' | 'r' => {
+                        // 选项之后剩余的字符就是它的值，例如 -efoo、-A2；如果没有剩余字符，则取下一个参数
+                        let flag = format!("-{}", c);
+                        let rest = &arg[i + 1..];
+                        let inline = if rest.is_empty() { None } else { Some(rest.to_string()) };
+                        match c {
+                            'e' => patterns.push(take_value(&flag, inline, &mut args)?),
+                            'f' => {
+                                patterns.extend(read_patterns(&take_value(&flag, inline, &mut args)?)?);
+                                pattern_file = true;
+                            }
+                            'A' => after_context = take_number(&flag, inline, &mut args)?,
+                            'B' => before_context = take_number(&flag, inline, &mut args)?,
+                            'j' => threads = take_number(&flag, inline, &mut args)?,
+                            'm' => max_count = Some(take_number(&flag, inline, &mut args)?),
+                            'r' => replace = Some(take_value(&flag, inline, &mut args)?),
+                            _ => {
+                                after_context = take_number(&flag, inline, &mut args)?;
+                                before_context = after_context;
+                            }
+                        }
+                        break;
+                    }
+                    _ => return Err(ArgsError::UnknownFlag(format!("-{}", c))),
+                }
+            }
+        } else {
+            // 单独的 - 也是普通参数
+            positional.push(arg);
+        }
+    }
+
+    let mut positional = positional.into_iter();
+
+    // 没有通过 -e 或 -f 指定模式时，第一个位置参数就是模式
+    // -f 指定的文件可能是空的，这时不匹配任何内容，但也不会把第一个位置参数当作模式
+    if patterns.is_empty() && !pattern_file {
+        match positional.next() {
+            Some(v) => patterns.push(v),
+            None => return Err(ArgsError::MissingPattern),
+        }
+    }
+
+    // 剩下的位置参数都是需要搜索的文件，至少需要一个
+    let paths: Vec<String> = positional.collect();
+    if paths.is_empty() {
+        return Err(ArgsError::MissingFilename);
+    }
+
+    // 多行模式下的匹配可能跨越多行，"不匹配的行"没有明确的含义
+    if multiline && invert {
+        return Err(ArgsError::ConflictingFlags("--multiline".to_string(), "--invert-match".to_string()));
+    }
+    // -v 选出的行没有匹配，也就没有可以替换的内容
+    // 多行模式的替换可能改变行数，预览时的行号会和原文件对不上，所以暂时不支持
+    if replace.is_some() {
+        if invert {
+            return Err(ArgsError::ConflictingFlags("--replace".to_string(), "--invert-match".to_string()));
+        }
+        if multiline {
+            return Err(ArgsError::ConflictingFlags("--replace".to_string(), "--multiline".to_string()));
+        }
+    } else if in_place {
+        return Err(ArgsError::RequiresFlag("--in-place".to_string(), "--replace".to_string()));
+    }
+
+    let case_sensitive = match case_mode {
+        Some(mode) => mode.is_case_sensitive(&patterns),
+        None => !env_case_insensitive,
+    };
+
+    Ok(Config {
+        patterns,
+        paths,
+        case_sensitive,
+        regex,
+        boundary,
+        multiline,
+        include,
+        exclude,
+        line_number,
+        byte_offset,
+        before_context,
+        after_context,
+        count,
+        with_pattern,
+        files_with_matches,
+        max_count,
+        invert,
+        threads,
+        color,
+        json,
+        replace,
+        in_place,
+        no_ignore,
+        text,
+    })
+}
+
+// 取出选项的值：优先使用内联的值，否则使用下一个参数
+fn take_value<I>(flag: &str, inline: Option<String>, args: &mut I) -> Result<String, ArgsError>
+where
+    I: Iterator<Item = String>,
+{
+    match inline {
+        Some(v) => Ok(v),
+        None => args.next().ok_or_else(|| ArgsError::MissingValue(flag.to_string())),
+    }
+}
+
+// 读取 -f 指定的模式文件，每一行是一个模式，空行会被忽略
+fn read_patterns(path: &str) -> Result<Vec<String>, ArgsError> {
+    let content = fs::read_to_string(path)
+        .map_err(|e| ArgsError::PatternFile(path.to_string(), e.to_string()))?;
+    Ok(content.lines()
+        .filter(|line| !line.is_empty())
+        .map(|line| line.to_string())
+        .collect())
+}
+
+// 取出选项的值并解析为非负整数
+fn take_number<I>(flag: &str, inline: Option<String>, args: &mut I) -> Result<usize, ArgsError>
+where
+    I: Iterator<Item = String>,
+{
+    let value = take_value(flag, inline, args)?;
+    value.parse().map_err(|_| ArgsError::InvalidValue(flag.to_string(), value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn flags_and_patterns() {
+        let config = parse(args(&["-inv", "-e", "foo", "-ebar", "--include=*.rs", "src"]), false).unwrap();
+
+        assert_eq!(config.patterns, vec!["foo", "bar"]);
+        assert_eq!(config.paths, vec!["src"]);
+        assert!(!config.case_sensitive);
+        assert!(config.line_number && config.invert && !config.count);
+        assert_eq!(config.include, vec!["*.rs"]);
+        assert!(!config.no_ignore && !config.text);
+
+        let config = parse(args(&["--no-ignore", "-a", "foo", "src"]), false).unwrap();
+        assert!(config.no_ignore && config.text);
+    }
+
+    #[test]
+    fn context_flags() {
+        let config = parse(args(&["-A2", "-B", "1", "a", "b"]), false).unwrap();
+        assert_eq!((config.before_context, config.after_context), (1, 2));
+
+        let config = parse(args(&["--context=3", "a", "b"]), false).unwrap();
+        assert_eq!((config.before_context, config.after_context), (3, 3));
+
+        assert_eq!(
+            parse(args(&["-C", "x", "a", "b"]), false).err(),
+            Some(ArgsError::InvalidValue("-C".to_string(), "x".to_string()))
+        );
+    }
+
+    #[test]
+    fn several_paths() {
+        let config = parse(args(&["-j", "4", "a", "src", "poem.txt", "-"]), false).unwrap();
+        assert_eq!(config.paths, vec!["src", "poem.txt", "-"]);
+        assert_eq!(config.threads, 4);
+    }
+
+    #[test]
+    fn color_flag() {
+        assert_eq!(parse(args(&["a", "b"]), false).unwrap().color, ColorChoice::Auto);
+        assert_eq!(parse(args(&["--color", "a", "b"]), false).unwrap().color, ColorChoice::Auto);
+        assert_eq!(parse(args(&["--color=never", "a", "b"]), false).unwrap().color, ColorChoice::Never);
+        assert_eq!(
+            parse(args(&["--color=red", "a", "b"]), false).err(),
+            Some(ArgsError::InvalidValue("--color".to_string(), "red".to_string()))
+        );
+    }
+
+    #[test]
+    fn pattern_file() {
+        let path = std::env::temp_dir().join("minigrep-args-patterns.txt");
+        fs::write(&path, "add\n\nadd_one\r\npad\n").unwrap();
+        let path = path.to_str().unwrap();
+
+        let config = parse(args(&["-f", path, "-e", "sub", "src"]), false).unwrap();
+        assert_eq!(config.patterns, vec!["add", "add_one", "pad", "sub"]);
+        assert_eq!(config.paths, vec!["src"]);
+
+        assert!(matches!(
+            parse(args(&["-f", "no-such-patterns.txt", "src"]), false),
+            Err(ArgsError::PatternFile(..))
+        ));
+    }
+
+    #[test]
+    fn cli_overrides_env() {
+        assert!(!parse(args(&["a", "b"]), true).unwrap().case_sensitive);
+        assert!(parse(args(&["-s", "a", "b"]), true).unwrap().case_sensitive);
+        assert!(!parse(args(&["-i", "a", "b"]), false).unwrap().case_sensitive);
+    }
+
+    #[test]
+    fn smart_case() {
+        assert!(!parse(args(&["-S", "nobody", "b"]), false).unwrap().case_sensitive);
+        assert!(parse(args(&["-S", "Nobody", "b"]), true).unwrap().case_sensitive);
+        // 最后出现的大小写选项生效
+        assert!(!parse(args(&["-S", "-i", "Nobody", "b"]), false).unwrap().case_sensitive);
+    }
+
+    #[test]
+    fn matching_modes() {
+        let config = parse(args(&["-Ewx", "-F", "a", "b"]), false).unwrap();
+        assert!(!config.regex);
+        assert_eq!(config.boundary, Boundary::Line);
+        assert!(parse(args(&["--multiline", "a", "b"]), false).unwrap().multiline);
+        assert_eq!(
+            parse(args(&["-Uv", "a", "b"]), false).err(),
+            Some(ArgsError::ConflictingFlags("--multiline".to_string(), "--invert-match".to_string()))
+        );
+    }
+
+    #[test]
+    fn replace_flags() {
+        let config = parse(args(&["-r", "$1", "-E", "(a)", "b"]), false).unwrap();
+        assert_eq!(config.replace.as_deref(), Some("$1"));
+        assert!(!config.in_place);
+        assert!(parse(args(&["--replace=", "--in-place", "a", "b"]), false).unwrap().in_place);
+        assert_eq!(
+            parse(args(&["--in-place", "a", "b"]), false).err(),
+            Some(ArgsError::RequiresFlag("--in-place".to_string(), "--replace".to_string()))
+        );
+        assert_eq!(
+            parse(args(&["-v", "-rx", "a", "b"]), false).err(),
+            Some(ArgsError::ConflictingFlags("--replace".to_string(), "--invert-match".to_string()))
+        );
+    }
+
+    #[test]
+    fn double_dash() {
+        let config = parse(args(&["--", "-v", "poem.txt"]), false).unwrap();
+        assert_eq!(config.patterns, vec!["-v"]);
+        assert!(!config.invert);
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(parse(args(&["-z", "a", "b"]), false).err(), Some(ArgsError::UnknownFlag("-z".to_string())));
+        assert_eq!(parse(args(&["a", "b", "-e"]), false).err(), Some(ArgsError::MissingValue("-e".to_string())));
+        assert_eq!(parse(args(&["--count=1", "a", "b"]), false).err(), Some(ArgsError::UnexpectedValue("--count".to_string())));
+        assert_eq!(parse(args(&["a"]), false).err(), Some(ArgsError::MissingFilename));
+        assert_eq!(parse(args(&["--help"]), false).err(), Some(ArgsError::Help));
+    }
+}
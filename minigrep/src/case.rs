@@ -0,0 +1,104 @@
+// 不区分大小写的匹配
+// 之前的做法是把 query 转换为小写，然后和原始的行进行比较，但是行本身并没有转换，所以只能找到本来就是小写的行
+// 现在 query 和行都使用 Unicode 大小写折叠（case folding）进行规范化，两边的处理方式完全一致
+// 大小写折叠比 to_lowercase 更适合做比较，例如德语的 ß 会折叠为 ss，希腊语的 ς 和 σ 会折叠为同一个字符
+// 中文等没有大小写的字符在折叠前后保持不变
+
+use std::ops::Range;
+
+use caseless::Caseless;
+
+// 对字符串进行大小写折叠
+pub fn fold(s: &str) -> String {
+    s.chars().default_case_fold().collect()
+}
+
+// 折叠之后的一行文本
+// 折叠之后字节长度可能变化（例如 ß 变成 ss），所以需要记录折叠后的每个字节对应原始文本中的哪个字符
+pub struct Folded {
+    pub text: String,
+    // origin[i] 是折叠后第 i 个字节所属的原始字符的字节范围 (开始, 结束)
+    origin: Vec<(usize, usize)>,
+}
+
+impl Folded {
+    pub fn new(line: &str) -> Folded {
+        let mut text = String::with_capacity(line.len());
+        let mut origin = Vec::with_capacity(line.len());
+
+        for (start, c) in line.char_indices() {
+            let end = start + c.len_utf8();
+            for f in std::iter::once(c).default_case_fold() {
+                text.push(f);
+                origin.extend(std::iter::repeat_n((start, end), f.len_utf8()));
+            }
+        }
+
+        Folded { text, origin }
+    }
+
+    // 把折叠后文本中的字节范围转换为原始文本中的字节范围
+    pub fn original_span(&self, span: Range<usize>) -> Range<usize> {
+        if span.is_empty() {
+            // 空的匹配没有对应的字符，使用它后面那个字符的开始位置
+            let pos = self.origin.get(span.start).map_or_else(
+                || self.origin.last().map_or(0, |o| o.1),
+                |o| o.0,
+            );
+            return pos..pos;
+        }
+        self.origin[span.start].0..self.origin[span.end - 1].1
+    }
+}
+
+// 在 line 中查找已经折叠过的 folded_query，返回匹配在原始 line 中的字节范围
+pub fn find_folded(folded_query: &str, line: &str) -> Option<Range<usize>> {
+    let folded = Folded::new(line);
+    let start = folded.text.find(folded_query)?;
+    Some(folded.original_span(start..start + folded_query.len()))
+}
+
+// 大小写模式，对应命令行中的 -s / -i / -S
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CaseMode {
+    Sensitive,
+    Insensitive,
+    // 所有模式中都没有大写字母时才不区分大小写
+    Smart,
+}
+
+impl CaseMode {
+    pub fn is_case_sensitive<P: AsRef<str>>(self, patterns: &[P]) -> bool {
+        match self {
+            CaseMode::Sensitive => true,
+            CaseMode::Insensitive => false,
+            CaseMode::Smart => !patterns.iter().all(|p| is_smart_case_insensitive(p.as_ref())),
+        }
+    }
+}
+
+// smart case：模式中没有大写字母时不区分大小写，否则区分大小写
+pub fn is_smart_case_insensitive(pattern: &str) -> bool {
+    !pattern.chars().any(char::is_uppercase)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unicode_folding() {
+        assert_eq!(Some(0..7), find_folded(&fold("STRASSE"), "Straße ist lang"));
+        assert_eq!(Some(7..18), find_folded(&fold("RUST 语言"), "学习 rust 语言"));
+        assert_eq!(None, find_folded(&fold("rust"), "学习 go 语言"));
+    }
+
+    #[test]
+    fn smart_case() {
+        assert!(is_smart_case_insensitive("nobody"));
+        assert!(is_smart_case_insensitive("语言"));
+        assert!(!is_smart_case_insensitive("Nobody"));
+        assert!(CaseMode::Smart.is_case_sensitive(&["nobody", "Who"]));
+        assert!(!CaseMode::Smart.is_case_sensitive(&["nobody", "语言"]));
+    }
+}
@@ -0,0 +1,141 @@
+// .gitignore / .ignore 文件的解析和匹配，规则和 git 保持一致：
+// 1. 空行和以 # 开头的行会被忽略，\# 和 \! 表示以 # 或 ! 开头的模式
+// 2. 以 ! 开头的模式表示重新包含之前被忽略的路径，例如 *.log 之后的 !keep.log
+// 3. 以 / 结尾的模式只匹配目录，例如 target/
+// 4. 包含 / 的模式相对于 ignore 文件所在的目录，例如 /build 和 docs/*.html；不包含 / 的模式可以匹配任意一层目录中的名字
+// 5. 同一个文件中后面的规则优先，更深的目录中的 ignore 文件优先于上层目录中的
+//
+// 被忽略的目录不会再被遍历，所以和 git 一样，无法通过 ! 重新包含被忽略目录中的文件
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use globset::{GlobBuilder, GlobMatcher};
+
+// 一个 ignore 文件中的所有规则
+pub struct Gitignore {
+    // 匹配时使用的是相对于 ignore 文件所在目录的路径
+    // 遍历时得到的是相对于搜索根目录的路径，需要先去掉 strip 前缀（根目录中更深的 ignore 文件），
+    // 或者加上 prepend 前缀（根目录之外、上层目录中的 ignore 文件）
+    strip: PathBuf,
+    prepend: PathBuf,
+    rules: Vec<Rule>,
+}
+
+struct Rule {
+    glob: GlobMatcher,
+    // ! 开头的模式
+    negated: bool,
+    // / 结尾的模式
+    dir_only: bool,
+}
+
+impl Gitignore {
+    // 解析 ignore 文件的内容，无法解析的模式会打印警告然后跳过，不影响其他规则
+    pub fn new(content: &str, source: &Path, strip: PathBuf, prepend: PathBuf) -> Gitignore {
+        let mut rules = vec![];
+        for line in content.lines() {
+            match parse_rule(line) {
+                Ok(Some(rule)) => rules.push(rule),
+                Ok(None) => {}
+                Err(e) => eprintln!("{}: invalid pattern '{}': {}", source.display(), line, e),
+            }
+        }
+        Gitignore { strip, prepend, rules }
+    }
+
+    // 读取 path 处的 ignore 文件，文件不存在、无法读取或者没有任何规则时返回 None
+    pub fn from_file(path: &Path, strip: PathBuf, prepend: PathBuf) -> Option<Gitignore> {
+        let content = fs::read_to_string(path).ok()?;
+        let ignore = Gitignore::new(&content, path, strip, prepend);
+        if ignore.rules.is_empty() {
+            None
+        } else {
+            Some(ignore)
+        }
+    }
+
+    // relative 是相对于搜索根目录的路径
+    // 返回 Some(true) 表示被忽略，Some(false) 表示被 ! 重新包含，None 表示这个文件中没有规则匹配它
+    pub fn matched(&self, relative: &Path, is_dir: bool) -> Option<bool> {
+        let path = self.prepend.join(relative.strip_prefix(&self.strip).ok()?);
+        // 从后向前查找，最后一条匹配的规则生效
+        self.rules.iter()
+            .rev()
+            .find(|rule| (is_dir || !rule.dir_only) && rule.glob.is_match(&path))
+            .map(|rule| !rule.negated)
+    }
+}
+
+// 解析一行，空行和注释返回 Ok(None)
+fn parse_rule(line: &str) -> Result<Option<Rule>, globset::Error> {
+    // 行尾的空格和 \r 都会被忽略
+    let mut pattern = line.trim_end();
+    if pattern.is_empty() || pattern.starts_with('#') {
+        return Ok(None);
+    }
+
+    // 去掉开头的 ! 或者转义用的 \
+    let negated = pattern.starts_with('!');
+    if negated || pattern.starts_with("\\#") || pattern.starts_with("\\!") {
+        pattern = &pattern[1..];
+    }
+
+    let dir_only = pattern.ends_with('/');
+    let pattern = pattern.trim_end_matches('/');
+    if pattern.is_empty() {
+        return Ok(None);
+    }
+
+    // 包含 / 的模式相对于 ignore 文件所在的目录，否则可以匹配任意一层目录
+    let glob = if pattern.contains('/') {
+        pattern.trim_start_matches('/').to_string()
+    } else {
+        format!("**/{}", pattern)
+    };
+    // literal_separator：* 不能匹配 /，只有 ** 可以跨越多层目录，和 git 一致
+    let glob = GlobBuilder::new(&glob)
+        .literal_separator(true)
+        .backslash_escape(true)
+        .build()?
+        .compile_matcher();
+
+    Ok(Some(Rule { glob, negated, dir_only }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ignore(content: &str) -> Gitignore {
+        Gitignore::new(content, Path::new(".gitignore"), PathBuf::new(), PathBuf::new())
+    }
+
+    #[test]
+    fn rules() {
+        let ignore = ignore("# build output\ntarget/\n/Cargo.lock\n*.log\n!keep.log\ndocs/*.html\n\\#notes\n");
+
+        assert_eq!(Some(true), ignore.matched(Path::new("target"), true));
+        assert_eq!(Some(true), ignore.matched(Path::new("crates/core/target"), true));
+        assert_eq!(None, ignore.matched(Path::new("target"), false));
+        assert_eq!(Some(true), ignore.matched(Path::new("Cargo.lock"), false));
+        assert_eq!(None, ignore.matched(Path::new("crates/core/Cargo.lock"), false));
+        assert_eq!(Some(true), ignore.matched(Path::new("logs/today.log"), false));
+        assert_eq!(Some(false), ignore.matched(Path::new("logs/keep.log"), false));
+        assert_eq!(Some(true), ignore.matched(Path::new("docs/index.html"), false));
+        assert_eq!(None, ignore.matched(Path::new("docs/api/index.html"), false));
+        assert_eq!(Some(true), ignore.matched(Path::new("#notes"), false));
+    }
+
+    #[test]
+    fn nested_and_outer_files() {
+        // src/.gitignore 中的规则只作用于 src 目录中的路径
+        let nested = Gitignore::new("/generated.rs\n", Path::new("src/.gitignore"), PathBuf::from("src"), PathBuf::new());
+        assert_eq!(Some(true), nested.matched(Path::new("src/generated.rs"), false));
+        assert_eq!(None, nested.matched(Path::new("generated.rs"), false));
+
+        // 搜索 crates/core 时，仓库根目录中的 .gitignore 需要看到 crates/core 前缀
+        let outer = Gitignore::new("/crates/core/out\n", Path::new(".gitignore"), PathBuf::new(), PathBuf::from("crates/core"));
+        assert_eq!(Some(true), outer.matched(Path::new("out"), true));
+    }
+}
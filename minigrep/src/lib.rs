@@ -1,41 +1,229 @@
 use std::error::Error;
 // 引入 env 模块
-use std::{env, process};
-
-use std::env::Args;
+use std::env;
 
+use std::collections::HashSet;
 // 引入 fs
-use std::fs;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, IsTerminal};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+// 正则表达式模式使用 regex crate
+use regex::Regex;
+// 多模式匹配使用 aho-corasick crate
+use aho_corasick::{AhoCorasick, MatchKind};
+
+// gzip 解压和 tar 归档放在 archive 模块中
+mod archive;
+// 命令行参数解析放在 args 模块中
+mod args;
+// 不区分大小写匹配使用的 Unicode 大小写折叠
+mod case;
+// 匹配逻辑放在 matcher 模块中
+mod matcher;
+// 多个文件的并行搜索放在 parallel 模块中
+mod parallel;
+// .gitignore 和 .ignore 文件的解析放在 gitignore 模块中
+mod gitignore;
+// 输出格式（行号、上下文等）放在 printer 模块中
+mod printer;
+// --in-place 直接修改文件的逻辑放在 replace 模块中
+mod replace;
+// 可以在其他程序中复用的 Searcher 和 Sink 放在 searcher 模块中
+mod searcher;
+// 目录遍历相关的逻辑放在 walk 模块中
+mod walk;
+
+pub use args::{ArgsError, USAGE};
+pub use case::CaseMode;
+pub use matcher::Hit;
+pub use searcher::{Searcher, SearcherBuilder, Sink, SinkContext, SinkMatch};
+use archive::Source;
+use printer::ColorChoice;
 
 pub fn run(config: Config) -> Result<(), Box<dyn Error>> {
 
-    // let content = fs::read_to_string(config.filename).expect("something went wrong reading the file");
+    // 模式只在这里准备一次（例如编译正则表达式），之后每一个文件、每一行都复用同一个 Searcher
+    let searcher = config.searcher().build()?;
+    let filter = walk::FileFilter::new(&config.include, &config.exclude)?;
+
+    // 把命令行中的所有路径展开为需要搜索的输入
+    // 搜索多个路径或者目录时，每一行结果前面都需要加上文件路径
+    let mut inputs = vec![];
+    let mut show_path = config.paths.len() > 1;
+    // 遍历目录时无法读取的子目录等，和无法读取的文件一样计入失败的数量
+    let mut failed = 0;
+    for name in &config.paths {
+        // - 表示从标准输入读取内容，例如 cat poem.txt | minigrep frog -
+        if name == "-" {
+            inputs.push(Input::Stdin);
+            continue;
+        }
+
+        let path = Path::new(name);
+        // 路径是目录时，递归搜索其中的所有文件
+        if path.is_dir() {
+            show_path = true;
+            let (files, errors) = walk::collect_files(path, &filter, !config.no_ignore);
+            inputs.extend(files.into_iter().map(Input::File));
+            failed += errors;
+        } else {
+            inputs.push(Input::File(path.to_path_buf()));
+        }
+    }
 
-    // 这里使用了 ? 运算符替代 expect，当 read_to_string 返回的是 Err 变体，? 运算符直接将错误返回值返回给函数的调用者
-    let content = fs::read_to_string(config.filename)?;
-    // println!("With text : \n{}", content);
+    // --in-place 时同一个文件只改写一次，例如同时给出了目录和其中的文件，或者两个目录有重叠
+    // 否则两个线程会同时改写同一个文件，后一次还可能在已经替换过的内容上再替换一次
+    if config.in_place {
+        let mut seen = HashSet::new();
+        inputs.retain(|input| match input {
+            Input::File(path) => seen.insert(fs::canonicalize(path).unwrap_or_else(|_| path.clone())),
+            Input::Stdin => true,
+        });
+    }
 
-    let result = if config.case_sensitive {
-        search(&config.query, &content)
-    } else {
-        search_case_insensitive(&config.query, &content)
+    // --color=auto 时，只有标准输出是终端才使用颜色，重定向到文件或者管道时输出纯文本
+    let color = match config.color {
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+        ColorChoice::Auto => io::stdout().is_terminal(),
     };
 
-    for line in result {
-        println!("{}", line);
+    // 多个文件交给 parallel 模块并行搜索，输出顺序和输入的顺序保持一致
+    // 无法读取的文件只打印错误信息，然后继续搜索其他文件，最后再把失败的数量作为错误返回
+    failed += parallel::search_inputs(&config, &searcher, &inputs, show_path, color);
+    if failed > 0 {
+        return Err(format!("{} file(s) could not be searched", failed).into());
     }
 // 成功时返回的是 ()，因此这里需要使用 Ok 变体包裹空元祖
     Ok(())
 }
 
-// 使用结构体来存储关联性很强的两个字段：query 和 filename
+// 一个需要搜索的输入：标准输入或者文件
+pub(crate) enum Input {
+    Stdin,
+    File(PathBuf),
+}
+
+impl Input {
+    // 输出结果时使用的名字，和 grep 一样，标准输入显示为 (standard input)
+    fn label(&self) -> String {
+        match self {
+            Input::Stdin => "(standard input)".to_string(),
+            Input::File(path) => path.display().to_string(),
+        }
+    }
+
+    // 之前使用 fs::read_to_string 一次性把整个文件读入内存，遇到几个 GB 的日志文件或者不是合法 UTF-8 的文件就会失败
+    // 现在改为通过 BufReader 逐行读取，内存占用只和最长的一行有关
+    // gzip 压缩的内容会被透明地解压，tar 归档则交给调用者逐个搜索其中的文件
+    fn open(&self) -> io::Result<Source> {
+        match self {
+            Input::Stdin => archive::detect(Box::new(io::stdin().lock()), None),
+            Input::File(path) => archive::detect(Box::new(BufReader::new(File::open(path)?)), Some(path)),
+        }
+    }
+}
+
+// 和 grep、ripgrep 一样，文件开头包含 NUL 字节时认为是二进制文件
+// 文本文件（包括 UTF-8 编码的中文）中几乎不会出现 NUL，而大多数二进制格式的开头都有
+pub(crate) fn is_binary(head: &[u8]) -> bool {
+    head[..head.len().min(BINARY_CHECK_LEN)].contains(&0)
+}
+
+// 判断是否是二进制文件时检查的字节数
+const BINARY_CHECK_LEN: usize = 8 * 1024;
+
+// 从任意实现了 BufRead 的来源（文件、标准输入等）逐行读取，对每一行调用 f(行号, 字节偏移量, 内容)
+// f 返回 false 时停止读取，例如 -m 已经达到上限，或者 -l 已经找到了匹配
+// 不是合法 UTF-8 的行会通过 from_utf8_lossy 把非法字节替换为 U+FFFD，而不是让整个搜索失败
+pub fn for_each_line<R, F>(mut reader: R, mut f: F) -> io::Result<()>
+where
+    R: BufRead,
+    F: FnMut(usize, usize, &str) -> bool,
+{
+    let mut buf = vec![];
+    let mut line_number = 0;
+    let mut byte_offset = 0;
+
+    loop {
+        buf.clear();
+        // read_until 读取到 \n（包含）为止，返回 0 表示已经读完
+        let read = reader.read_until(b'\n', &mut buf)?;
+        if read == 0 {
+            return Ok(());
+        }
+        line_number += 1;
+
+        // 和 str::lines 一样去掉行尾的 \n 或 \r\n
+        let mut end = buf.len();
+        if buf[..end].ends_with(b"\n") {
+            end -= 1;
+        }
+        if buf[..end].ends_with(b"\r") {
+            end -= 1;
+        }
+
+        let line = String::from_utf8_lossy(&buf[..end]);
+        if !f(line_number, byte_offset, &line) {
+            return Ok(());
+        }
+        byte_offset += read;
+    }
+}
+
+// 最初的 Config 只有 query 和 filename 两个关联性很强的字段
+// 现在它保存一次运行需要的全部配置：搜索模式、要搜索的路径，以及命令行选项决定的匹配方式、输出格式和遍历目录的规则
 pub struct Config {
-    query: String,
-    filename: String,
+    // 搜索模式，可以通过 -e 指定多个，也可以通过 -f 从文件中读取，任意一个匹配即可
+    patterns: Vec<String>,
+    // 需要搜索的文件或目录，可以有多个，- 表示标准输入
+    paths: Vec<String>,
     // 搜索时是否忽略大小写，为 true，表示忽略大小写，为 false，表示识别大小写
     case_sensitive: bool,
+    // 是否将 query 当作正则表达式，通过命令行参数 -E 或者 --regex 开启，默认为 false，即子串匹配
+    regex: bool,
+    // -w / -x：匹配必须是完整的单词或者完整的一行
+    boundary: matcher::Boundary,
+    // -U：多行模式，模式可以跨越换行符进行匹配
+    multiline: bool,
+    // 搜索目录时使用的 glob 过滤条件，分别通过 --include 和 --exclude 指定，都可以出现多次
+    include: Vec<String>,
+    exclude: Vec<String>,
+    // -n：输出行号
+    line_number: bool,
+    // -b：输出每一行在文件中的字节偏移量
+    byte_offset: bool,
+    // -B / -A：匹配行之前、之后输出的上下文行数，-C 同时设置这两个值
+    before_context: usize,
+    after_context: usize,
+    // -c：只输出匹配的行数
+    count: bool,
+    // -l：只输出包含匹配的文件名
+    files_with_matches: bool,
+    // -m：每个文件最多选出多少行
+    max_count: Option<usize>,
+    // -v：选出不匹配的行
+    invert: bool,
+    // --with-pattern：在匹配的行前面输出命中的模式
+    with_pattern: bool,
+    // -j：并行搜索使用的线程数，为 0 时根据 CPU 数量自动决定
+    threads: usize,
+    // --color：是否高亮匹配的内容
+    color: ColorChoice,
+    // --json：每个匹配输出一个 JSON 对象
+    json: bool,
+    // --replace：把每个匹配替换为这段文本之后再输出
+    replace: Option<String>,
+    // --in-place：直接修改文件，而不是输出替换之后的行
+    in_place: bool,
+    // --no-ignore：不遵守 .gitignore 和 .ignore 文件
+    no_ignore: bool,
+    // -a：把二进制文件也当作文本搜索
+    text: bool,
 }
-// 包含 query 和 filename 字段的结构体 Config
+// 包含 patterns 和 paths 字段的结构体 Config
 impl Config {
     // 之前的 parse_config 方法，实际上就返回了一个 Config 的实例
     // 那么其作用就是一个构造器
@@ -49,65 +237,92 @@ impl Config {
     // env::args 函数的标准库文档表明，它会返回一个类型为 std::env::Args 的迭代器
     // 据此，我们将 new 函数签名中的 args 参数的类型从 &[String] 类型改为了 std::env::Args 类型
     // 由于我们获得了 args 的所有权并会在函数体中通过迭代来改变它，所以我们需要在 args 参数前指定 mut 关键字来使其可变
-    pub fn new(mut args: std::env::Args) -> Result<Config, &'static str> {
-
-        // 添加错误处理
-        // if args.len() < 3 {
-        //     return Err("not enough arguments");
-        // }
-
-        // 使用 clone 是因为 new 函数并不持有 args 参数内元素的所有权
-        // 我们获得的仅仅是一个 String 序列的切片
-        // 为了返回 Config 实例的所有权，我们必须要克隆 Config 的 query 字段和 filename 字段中的值
-        // 只有这样，Config 才能拥有这些值的所有权
-
-
-        // 学习了迭代器之后，我们现在可以在 new 函数中直接使用迭代器作为参数来获取其所有权而无须再借用切片
-        // 我们还可以使用迭代器附带的功能来进行长度检查和索引
-        // 这将使 Config::new 函数的责任范围更加明确，因为我们通过迭代器将读取具体值的工作分离了出去
-
-
-        // let query = args[1].clone();
-        // let filename = args[2].clone();
-
 
+    // 参数越来越多之后，按顺序读取位置参数已经不够用了，具体的解析工作交给 args 模块
+    // 这里接收任意产生 String 的迭代器，而不仅仅是 std::env::Args，这样测试中也可以方便地构造参数
+    // 解析失败时返回 ArgsError，而不是 &'static str，调用者可以区分不同的错误
+    pub fn new<I>(mut args: I) -> Result<Config, ArgsError>
+    where
+        I: Iterator<Item = String>,
+    {
         // args 迭代器的第一个元素是二进制程序名，我们不需要，所以这里直接调用 next 方法，跳过第一个元素
         args.next();
 
+        // 命令行中的 -i / -s 优先于 CASE_INSENSITIVE 环境变量
+        let env_case_insensitive = env::var("CASE_INSENSITIVE").is_ok();
+        args::parse(args, env_case_insensitive)
+    }
 
-        // 再次调用 next 来取得用于 Config 中 query 字段的值
-        // 如果 next 返回一个 Some 变体，我们就会使用 match 来提取这个值
-        // 如果它返回的是 None，则表明用户没有提供足够的参数，返回一个 Err 变体
-        let query = match args.next() {
-            Some(v) => v,
-            None => return Err("Didn't get a query string")
-        };
-
-        let filename = match args.next() {
-            Some(v) => v,
-            None => return Err("Didn't get a file name")
-        };
+    // 根据命令行参数配置 Searcher，大小写模式在解析参数时已经确定了
+    // JSON、-c 和 -l 的输出中都没有上下文，所以不需要 Searcher 去计算它
+    pub(crate) fn searcher(&self) -> SearcherBuilder {
+        let context = !(self.json || self.count || self.files_with_matches);
+        Searcher::builder()
+            .patterns(self.patterns.iter().cloned())
+            .regex(self.regex)
+            .case(if self.case_sensitive { CaseMode::Sensitive } else { CaseMode::Insensitive })
+            .word(self.boundary == matcher::Boundary::Word)
+            .whole_line(self.boundary == matcher::Boundary::Line)
+            .multiline(self.multiline)
+            .before_context(if context { self.before_context } else { 0 })
+            .after_context(if context { self.after_context } else { 0 })
+            .max_count(self.max_count)
+            .invert(self.invert)
+            .text(self.text)
+            .replace(self.replace.clone())
+    }
+}
 
+// 一条匹配记录，除了匹配的行本身之外，还记录了它在文件中的位置，方便在编辑器中直接跳转
+#[derive(Debug, Clone, PartialEq)]
+pub struct Match<'a> {
+    // 行号，从 1 开始
+    pub line_number: usize,
+    // 这一行的第一个字节在整个内容中的偏移量
+    pub byte_offset: usize,
+    // 匹配到的内容在这一行中的字节范围
+    pub span: Range<usize>,
+    // 命中的是第几个模式，只有一个模式的搜索函数中总是 0
+    pub pattern: usize,
+    pub line: &'a str,
+}
 
-        let case_sensitive = env::var("CASE_INSENSITIVE").is_err();
-        Ok(Config {
-            query,
-            filename,
-            case_sensitive
+// 和 str::lines 一样按行切分（去掉 \n 或 \r\n），同时产生行号和每一行的字节偏移量
+pub(crate) fn numbered_lines(content: &str) -> impl Iterator<Item = (usize, usize, &str)> {
+    let mut offset = 0;
+    content.split_inclusive('\n')
+        .enumerate()
+        .map(move |(index, raw)| {
+            let start = offset;
+            offset += raw.len();
+            let line = raw.strip_suffix('\n').unwrap_or(raw);
+            let line = line.strip_suffix('\r').unwrap_or(line);
+            // enumerate 从 0 开始计数，而行号从 1 开始
+            (index + 1, start, line)
         })
-    }
 }
 
-pub fn search<'a>(query: &'a str, content: &'a str) -> Vec<&'a str> {
-
+// 对每一行调用 find，找到匹配的位置后生成 Match 记录
+fn collect_matches<'a, F>(content: &'a str, find: F) -> Vec<Match<'a>>
+where
+    F: Fn(&str) -> Option<Range<usize>>,
+{
+    numbered_lines(content)
+        .filter_map(|(line_number, byte_offset, line)| {
+            find(line).map(|span| Match { line_number, byte_offset, span, pattern: 0, line })
+        })
+        .collect()
+}
 
+pub fn search<'a>(query: &str, content: &'a str) -> Vec<Match<'a>> {
 
     // 使用迭代器适配器优化查找过程
     // 函数式编程风格倾向于在程序中最小化可变状态的数量来使代码更加清晰
     // 消除可变状态也使我们可以在未来通过并行化来提升搜索效率，因为我们不再需要考虑并发访问 results 动态数组时的安全问题了
-    content.lines()
-        .filter(|x| {x.contains(query)})
-        .collect()
+    // 现在返回的是 Match 记录而不只是行本身，所以这里使用 find 代替 contains，以便得到匹配的位置
+    collect_matches(content, |line| {
+        line.find(query).map(|start| start..start + query.len())
+    })
 
 
     // let mut result = vec![];
@@ -122,14 +337,14 @@ pub fn search<'a>(query: &'a str, content: &'a str) -> Vec<&'a str> {
     // result
 }
 
-pub fn search_case_insensitive<'a>(query: &'a str, content: &'a str) -> Vec<&'a str> {
+pub fn search_case_insensitive<'a>(query: &str, content: &'a str) -> Vec<Match<'a>> {
 
-    let query = query.to_lowercase();
+    // 之前只把 query 转换为小写，而没有转换 line，所以只能找到本来就是小写的行
+    // 现在 query 和 line 都经过相同的 Unicode 大小写折叠，再进行比较
+    let query = case::fold(query);
 
     // 使用迭代器适配器优化查找过程
-    content.lines()
-        .filter(|line| {line.contains(query)})
-        .collect()
+    collect_matches(content, |line| case::find_folded(&query, line))
 
     // let mut result = vec![];
     //
@@ -143,3 +358,168 @@ pub fn search_case_insensitive<'a>(query: &'a str, content: &'a str) -> Vec<&'a
     // result
 
 }
+
+// 使用正则表达式进行搜索
+// 接收的是已经编译好的 Regex，这样 run 函数只需要编译一次，而不是每一行都编译一次
+pub fn search_regex<'a>(re: &Regex, content: &'a str) -> Vec<Match<'a>> {
+    collect_matches(content, |line| re.find(line).map(|m| m.range()))
+}
+
+// 同时搜索多个模式，每一行只扫描一遍
+// 使用 Aho-Corasick 自动机代替对每个模式分别调用一次 search，模式再多也不需要重复扫描内容
+// 每一行中的每个匹配都会生成一条 Match 记录，pattern 是命中的模式在 patterns 中的序号
+pub fn search_patterns<'a, P: AsRef<str>>(patterns: &[P], content: &'a str) -> Vec<Match<'a>> {
+    let needles: Vec<&str> = patterns.iter().map(|p| p.as_ref()).collect();
+    let ac = AhoCorasick::builder()
+        .match_kind(MatchKind::LeftmostLongest)
+        .build(&needles)
+        .expect("literal patterns always build an automaton");
+
+    numbered_lines(content)
+        .flat_map(|(line_number, byte_offset, line)| {
+            ac.find_iter(line).map(move |m| Match {
+                line_number,
+                byte_offset,
+                span: m.range(),
+                pattern: m.pattern().as_usize(),
+                line,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines<'a>(matches: Vec<Match<'a>>) -> Vec<&'a str> {
+        matches.into_iter().map(|m| m.line).collect()
+    }
+
+    #[test]
+    fn case_sensitive() {
+        let query = "duct";
+        let content = "\
+Rust:
+safe, fast, productive.
+Pick three.
+Duct Tape.";
+
+        assert_eq!(
+            vec!["safe, fast, productive."],
+            lines(search(query, content))
+        )
+    }
+
+    #[test]
+    fn match_positions() {
+        let content = "Rust:\r\nsafe, fast, productive.\nPick three.";
+
+        assert_eq!(
+            vec![Match {
+                line_number: 2,
+                byte_offset: 7,
+                span: 15..19,
+                pattern: 0,
+                line: "safe, fast, productive.",
+            }],
+            search("duct", content)
+        );
+    }
+
+    #[test]
+    fn case_insensitive() {
+        let query = "rUsT";
+        let contents = "\
+Rust:
+safe, fast, productive.
+Pick three.
+Trust me.";
+
+        assert_eq!(
+            vec!["Rust:", "Trust me."],
+            lines(search_case_insensitive(query, contents))
+        );
+    }
+
+    // 回归测试：之前 search_case_insensitive 没有转换行的大小写，poem.txt 中以大写字母开头的 How 都找不到
+    #[test]
+    fn case_insensitive_poem() {
+        let poem = include_str!("../poem.txt");
+
+        assert_eq!(
+            vec!["How dreary to be somebody!", "How public, like a frog"],
+            lines(search_case_insensitive("how", poem))
+        );
+        assert_eq!(
+            vec!["I'm nobody! Who are you?", "Are you nobody, too?"],
+            lines(search_case_insensitive("NOBODY", poem))
+        );
+        assert_eq!(
+            vec!["Then there's a pair of us - don't tell!", "To tell your name the livelong day"],
+            lines(search_case_insensitive("TeLl", poem))
+        );
+    }
+
+    #[test]
+    fn case_insensitive_unicode() {
+        let contents = "\
+Rust 是一门系统编程语言
+学习 RUST 需要耐心
+Die Straße
+Go 语言";
+
+        assert_eq!(
+            vec!["Rust 是一门系统编程语言", "学习 RUST 需要耐心"],
+            lines(search_case_insensitive("rust", contents))
+        );
+        assert_eq!(vec!["Die Straße"], lines(search_case_insensitive("STRASSE", contents)));
+        assert_eq!(vec!["Go 语言"], lines(search_case_insensitive("GO 语言", contents)));
+    }
+
+    #[test]
+    fn many_patterns() {
+        let poem = include_str!("../poem.txt");
+        let found: Vec<(usize, &str)> = search_patterns(&["nobody", "frog", "bog"], poem)
+            .into_iter()
+            .map(|m| (m.line_number, &m.line[m.span]))
+            .collect();
+
+        assert_eq!(vec![(1, "nobody"), (2, "nobody"), (6, "frog"), (8, "bog")], found);
+    }
+
+    #[test]
+    fn streaming_lines() {
+        let content: &[u8] = b"Rust:\r\nsafe, \xff fast\nPick three.";
+        let mut lines = vec![];
+        for_each_line(content, |line_number, byte_offset, line| {
+            lines.push((line_number, byte_offset, line.to_string()));
+            true
+        }).unwrap();
+
+        assert_eq!(
+            vec![
+                (1, 0, "Rust:".to_string()),
+                (2, 7, "safe, \u{FFFD} fast".to_string()),
+                (3, 20, "Pick three.".to_string()),
+            ],
+            lines
+        );
+    }
+
+    #[test]
+    fn regex_search() {
+        let re = Regex::new(r"^fn \w+").unwrap();
+        let content = "\
+fn main() {
+    let f = 1;
+}
+fn helper_fn() {}
+// fn commented";
+
+        assert_eq!(
+            vec!["fn main() {", "fn helper_fn() {}"],
+            lines(search_regex(&re, content))
+        );
+    }
+}
@@ -20,15 +20,11 @@
 // 为了使 minigrep 可以读取传递给它的命令行参数值，我们需要使用 Rust 标准库提供的 std::env::args 函数
 // 这个函数会返回一个传递给 minigrep 的命令行参数迭代器（iterator），然后将迭代器产生的值，转换为集合（collection），例如：动态数组
 
-use std::error::Error;
 // 引入 env 模块
 use std::{env, process};
 
-// 引入 fs
-use std::fs;
-
 // 导入 库 crate
-use minigrep::{self, Config};
+use minigrep::{ArgsError, Config, USAGE};
 
 // Rust 社区开发了一套为将会逐渐臃肿的二进制程序进行关注点分离的指导性原则：
 // 1. 将程序拆分为 main.rs 和 lib.rs ，并将实际的业务逻辑放入 lib.rs
@@ -49,7 +45,14 @@ fn main() {
     // Config 的关联函数 new 现在接收的是迭代器，因此，这里直接将 args 参数传进去，而无需将其转换为 字符串数组
     let config = Config::new(args).unwrap_or_else(|err| {
 
+        // --help 不是真正的错误，打印帮助信息到标准输出，然后正常退出
+        if err == ArgsError::Help {
+            println!("{}", USAGE);
+            process::exit(0);
+        }
+
         eprintln!("Problem parsing arguments: {}", err);
+        eprintln!("\n{}", USAGE);
         process::exit(1);
     });
 
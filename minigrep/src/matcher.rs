@@ -0,0 +1,297 @@
+// 匹配器：根据配置决定一行文本是否匹配
+// 模式在创建 Matcher 时就准备好（转换大小写、编译正则表达式、构建自动机），之后每一行都复用它
+
+use std::error::Error;
+use std::ops::Range;
+
+use aho_corasick::{AhoCorasick, MatchKind};
+use regex::{Regex, RegexBuilder};
+
+use crate::case::{self, Folded};
+use crate::searcher::SearcherBuilder;
+
+// 一行中的一个匹配：匹配的字节范围，以及命中的是第几个模式
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hit {
+    pub span: Range<usize>,
+    pub pattern: usize,
+}
+
+// -w / -x 对匹配位置的额外要求
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Boundary {
+    None,
+    // -w：匹配的前后不能紧挨着单词字符（字母、数字和下划线），例如 add 不会匹配 add_one 和 padding
+    Word,
+    // -x：匹配必须是完整的一行
+    Line,
+}
+
+pub struct Matcher {
+    kind: Kind,
+    boundary: Boundary,
+    // 模式的数量，组合的正则表达式需要依次检查每个模式的分组
+    pattern_count: usize,
+}
+
+enum Kind {
+    // 单个模式、区分大小写的子串匹配，和 search 函数的行为一致
+    Literal(String),
+    // 多个子串模式，或者不区分大小写的子串模式
+    // 使用 Aho-Corasick 自动机，无论有多少个模式，每一行都只需要扫描一遍
+    // folded 为 true 时，自动机中保存的是经过大小写折叠的模式，匹配前也要对行进行折叠，和 search_case_insensitive 的行为一致
+    Automaton { ac: AhoCorasick, folded: bool },
+    // 正则表达式模式，有多个模式时会被组合成一个正则表达式，每个模式放在一个命名分组中
+    Regex { re: Regex, grouped: bool },
+}
+
+impl Matcher {
+    pub fn new(options: &SearcherBuilder) -> Result<Matcher, Box<dyn Error>> {
+        let patterns = &options.patterns;
+        let case_sensitive = options.case_sensitive();
+
+        let kind = if !options.regex && case_sensitive && patterns.len() == 1 {
+            Kind::Literal(patterns[0].clone())
+        } else if !options.regex || patterns.is_empty() {
+            // -f 指定的文件中可能没有任何模式，这时自动机不会匹配任何内容
+            let folded = !case_sensitive;
+            let needles: Vec<String> = if folded {
+                patterns.iter().map(|p| case::fold(p)).collect()
+            } else {
+                patterns.clone()
+            };
+            // LeftmostLongest：同一个位置可以匹配多个模式时，选择最长的那个，例如 add 和 add_one 同时存在时选择 add_one
+            let ac = AhoCorasick::builder()
+                .match_kind(MatchKind::LeftmostLongest)
+                .build(&needles)?;
+            Kind::Automaton { ac, folded }
+        } else {
+            // 多个模式使用 | 组合起来，任意一个模式匹配即可
+            // 每个模式放在名为 p0、p1…… 的分组中，匹配之后可以通过分组知道命中的是哪个模式
+            let grouped = patterns.len() > 1;
+            let mut alternation = if grouped {
+                patterns.iter()
+                    .enumerate()
+                    .map(|(i, p)| format!("(?P<p{}>{})", i, p))
+                    .collect::<Vec<_>>()
+                    .join("|")
+            } else {
+                patterns[0].clone()
+            };
+            // -x 时使用锚点，让正则表达式直接寻找完整的一行，而不是先找到较短的匹配再被拒绝
+            // -w 同理：最左边的匹配不是完整的单词时，正则表达式还会尝试同一位置上其他的分支，例如 a|ab 可以匹配单词 ab
+            // \b{start-half} 和 \b{end-half} 只要求前面（后面）不是单词字符，和 accepts 的检查一致
+            // 它们不消耗字符，也不增加分组，--replace 中 $1 等分组的编号不变
+            match options.boundary {
+                Boundary::Line => alternation = format!("^(?:{})$", alternation),
+                Boundary::Word => alternation = format!(r"\b{{start-half}}(?:{})\b{{end-half}}", alternation),
+                Boundary::None => {}
+            }
+
+            // 正则表达式的 case_insensitive 只做简单的大小写折叠（一个字符对应一个字符），ß 不会折叠为 ss
+            // 所以 -iE STRASSE 不会匹配 Straße，这和子串模式不同，USAGE 中也有说明
+            // 没有对行做完整的折叠，因为 --replace 中的分组会引用到折叠之后的文本，而不是原始文本
+            // 多行模式下，^ 和 $ 匹配每一行的开头和结尾，而不是整个文件的开头和结尾
+            let re = RegexBuilder::new(&alternation)
+                .case_insensitive(!case_sensitive)
+                .multi_line(options.multiline)
+                .build()?;
+            Kind::Regex { re, grouped }
+        };
+
+        Ok(Matcher { kind, boundary: options.boundary, pattern_count: patterns.len() })
+    }
+
+    // 返回这一行中所有不重叠的匹配，以及每个匹配命中的模式，用于高亮显示和 JSON 输出
+    // 多行模式下，line 是整个文件的内容
+    pub fn find_all(&self, line: &str) -> Vec<Hit> {
+        let mut hits = vec![];
+        let mut start = 0;
+        while let Some(hit) = self.find_at(line, start) {
+            // 空的匹配会在同一个位置反复出现，所以至少向后移动一个字符
+            start = if hit.span.is_empty() { next_char(line, hit.span.end) } else { hit.span.end };
+            hits.push(hit);
+            if start > line.len() {
+                break;
+            }
+        }
+        hits
+    }
+
+    // 把 line 中的 hits 替换为 replacement，返回替换之后的文本，以及替换进去的文本在新文本中的位置
+    // 正则表达式模式下，replacement 中可以使用 $1、${name} 引用分组，和 Regex::replace 的写法一致
+    // 有多个正则表达式模式时，组合时加入的分组也会参与编号，这时建议使用命名分组
+    pub fn replace(&self, line: &str, hits: &[Hit], replacement: &str) -> (String, Vec<Hit>) {
+        let mut out = String::with_capacity(line.len());
+        let mut spans = Vec::with_capacity(hits.len());
+        let mut last = 0;
+        for hit in hits {
+            out.push_str(&line[last..hit.span.start]);
+            let start = out.len();
+            match &self.kind {
+                // 从匹配的开始位置重新查找，得到的就是同一个匹配的分组
+                Kind::Regex { re, .. } => match re.captures_at(line, hit.span.start) {
+                    Some(caps) => caps.expand(replacement, &mut out),
+                    None => out.push_str(replacement),
+                },
+                _ => out.push_str(replacement),
+            }
+            spans.push(Hit { span: start..out.len(), pattern: hit.pattern });
+            last = hit.span.end;
+        }
+        out.push_str(&line[last..]);
+        (out, spans)
+    }
+
+    // 从 start 开始查找第一个满足 -w / -x 要求的匹配
+    // 不满足要求的匹配被拒绝之后，从它的下一个字符重新开始查找，例如在 add_one add 中查找单词 add
+    fn find_at(&self, line: &str, mut start: usize) -> Option<Hit> {
+        loop {
+            let hit = self.raw_find_at(line, start)?;
+            if self.accepts(line, &hit.span) {
+                return Some(hit);
+            }
+            start = next_char(line, hit.span.start);
+            if start > line.len() {
+                return None;
+            }
+        }
+    }
+
+    // 不考虑 -w / -x，从 start 开始查找第一个匹配，返回的位置是相对于整个 line 的
+    fn raw_find_at(&self, line: &str, start: usize) -> Option<Hit> {
+        let rest = &line[start..];
+        let hit = match &self.kind {
+            Kind::Literal(query) => rest.find(query.as_str())
+                .map(|i| Hit { span: i..i + query.len(), pattern: 0 }),
+            Kind::Automaton { ac, folded: false } => ac.find(rest)
+                .map(|m| Hit { span: m.range(), pattern: m.pattern().as_usize() }),
+            Kind::Automaton { ac, folded: true } => {
+                let folded = Folded::new(rest);
+                ac.find(&folded.text)
+                    .map(|m| Hit { span: folded.original_span(m.range()), pattern: m.pattern().as_usize() })
+            }
+            // 正则表达式使用 find_at，这样 \b、^ 等断言依然能看到 start 之前的内容
+            Kind::Regex { re, grouped: false } => {
+                return re.find_at(line, start).map(|m| Hit { span: m.range(), pattern: 0 });
+            }
+            Kind::Regex { re, grouped: true } => {
+                return re.captures_at(line, start).map(|caps| {
+                    // 找到第一个参与了匹配的分组，它的序号就是命中的模式
+                    let pattern = (0..self.pattern_count)
+                        .find(|i| caps.name(&format!("p{}", i)).is_some())
+                        .unwrap_or(0);
+                    Hit { span: caps.get(0).unwrap().range(), pattern }
+                });
+            }
+        };
+        hit.map(|hit| Hit { span: hit.span.start + start..hit.span.end + start, pattern: hit.pattern })
+    }
+
+    // 检查匹配是否满足 -w / -x 的要求
+    fn accepts(&self, line: &str, span: &Range<usize>) -> bool {
+        let before = line[..span.start].chars().next_back();
+        let after = line[span.end..].chars().next();
+        match self.boundary {
+            Boundary::None => true,
+            Boundary::Word => !before.is_some_and(is_word_char) && !after.is_some_and(is_word_char),
+            // 多行模式下 line 是整个文件，所以匹配的前后是换行符也可以
+            Boundary::Line => {
+                matches!(before, None | Some('\n'))
+                    && (after.is_none() || line[span.end..].starts_with('\n') || line[span.end..].starts_with("\r\n"))
+            }
+        }
+    }
+}
+
+// 和正则表达式中的 \w 一致：字母、数字（包括中文等 Unicode 字符）和下划线
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+// pos 之后下一个字符的位置，pos 已经是结尾时返回 len + 1
+fn next_char(line: &str, pos: usize) -> usize {
+    match line[pos..].chars().next() {
+        Some(c) => pos + c.len_utf8(),
+        None => line.len() + 1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matcher(args: &[&str]) -> Matcher {
+        let args = args.iter().map(|s| s.to_string());
+        Matcher::new(&crate::args::parse(args, false).unwrap().searcher()).unwrap()
+    }
+
+    fn find(matcher: &Matcher, line: &str) -> Option<Range<usize>> {
+        matcher.find_all(line).into_iter().next().map(|hit| hit.span)
+    }
+
+    fn hits(matcher: &Matcher, line: &str) -> Vec<(Range<usize>, usize)> {
+        matcher.find_all(line).into_iter().map(|hit| (hit.span, hit.pattern)).collect()
+    }
+
+    #[test]
+    fn automaton_reports_pattern() {
+        let m = matcher(&["-e", "add", "-e", "add_one", "-e", "pad", "f"]);
+        assert_eq!(vec![(4..11, 1), (14..17, 2)], hits(&m, "let add_one = pad;"));
+
+        let m = matcher(&["-i", "-e", "FROG", "-e", "straße", "f"]);
+        assert_eq!(vec![(0..4, 0), (5..12, 1)], hits(&m, "Frog STRASSE"));
+    }
+
+    #[test]
+    fn word_and_line() {
+        let m = matcher(&["-w", "add", "f"]);
+        assert_eq!(vec![(11..14, 0)], hits(&m, "add_one(); add(padding);"));
+        assert_eq!(None, find(&m, "padding add_one"));
+
+        let m = matcher(&["-wi", "-e", "ADD", "-e", "one", "f"]);
+        assert_eq!(vec![(0..3, 0), (12..15, 1)], hits(&m, "Add add_one one"));
+
+        let m = matcher(&["-x", "-e", "add", "-e", "add one", "f"]);
+        assert_eq!(Some(0..7), find(&m, "add one"));
+        assert_eq!(None, find(&m, "add one two"));
+
+        let m = matcher(&["-xE", "a|ab", "f"]);
+        assert_eq!(Some(0..2), find(&m, "ab"));
+
+        let m = matcher(&["-wE", "a|ab", "f"]);
+        assert_eq!(Some(0..2), find(&m, "ab"));
+        assert_eq!(vec![(0..1, 0), (2..4, 0)], hits(&m, "a ab abc"));
+
+        // 加上单词边界之后，$1 依然是模式中的第一个分组
+        let m = matcher(&["-wE", r"(\w+)=", "f"]);
+        let line = "a= b=1";
+        assert_eq!("<a> b=1", m.replace(line, &m.find_all(line), "<$1>").0);
+    }
+
+    #[test]
+    fn replace_hits() {
+        let m = matcher(&["-w", "add", "f"]);
+        let line = "add(add_one(x))";
+        let (replaced, spans) = m.replace(line, &m.find_all(line), "sum");
+        assert_eq!("sum(add_one(x))", replaced);
+        assert_eq!(vec![Hit { span: 0..3, pattern: 0 }], spans);
+
+        let m = matcher(&["-E", r"(\w+)\((\w+)\)", "f"]);
+        let line = "let y = add(x);";
+        let (replaced, spans) = m.replace(line, &m.find_all(line), "$2.$1()");
+        assert_eq!("let y = x.add();", replaced);
+        assert_eq!(vec![Hit { span: 8..15, pattern: 0 }], spans);
+    }
+
+    #[test]
+    fn regex_reports_pattern() {
+        let m = matcher(&["-E", "-e", r"fn \w+", "-e", r"let (\w+)", "f"]);
+        assert_eq!(vec![(0..7, 0), (10..15, 1)], hits(&m, "fn main { let x }"));
+
+        // 正则表达式只做简单的大小写折叠，和 USAGE 中的说明一致
+        assert_eq!(Some(0..7), find(&matcher(&["-i", "STRASSE", "f"]), "Straße"));
+        assert_eq!(None, find(&matcher(&["-iE", "STRASSE", "f"]), "Straße"));
+        assert_eq!(Some(0..7), find(&matcher(&["-iE", "STRAẞE", "f"]), "Straße"));
+    }
+}
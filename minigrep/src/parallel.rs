@@ -0,0 +1,291 @@
+// 并行搜索多个文件
+// search 系列函数都是纯函数，不依赖可变的共享状态，所以可以放心地在多个线程中同时调用
+// 工作线程通过一个原子计数器依次领取下一个文件，输出按照文件序号的顺序写入标准输出：
+// 1. 轮到的文件（排在它前面的文件都已经输出完了）边搜索边直接写入标准输出，只有一个输入或者 -j1 时总是这种情况，
+//    所以 tail -f 式的标准输入和几个 GB 的日志文件都能立即看到结果，内存占用也不会随输出增长
+// 2. 还没轮到的文件先把输出暂存起来，轮到它时再一次性写出
+// 这样无论线程数是多少，输出的顺序都和逐个搜索时完全一致
+//
+// 标准输出被关闭时（例如 minigrep ... | head -1）停止所有搜索并正常退出，而不是像 print! 那样 panic
+
+use std::collections::HashMap;
+use std::io::{self, BufReader, Write};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+use crate::archive::Source;
+use crate::printer::Printer;
+use crate::replace;
+use crate::searcher::{Searcher, Sink, SinkContext, SinkMatch};
+use crate::{Config, Input};
+
+// 搜索所有输入，返回无法读取的文件数量
+pub fn search_inputs(config: &Config, searcher: &Searcher, inputs: &[Input], show_path: bool, color: bool) -> usize {
+    let threads = thread_count(config.threads).min(inputs.len()).max(1);
+    let next = AtomicUsize::new(0);
+    // JSON 输出不包含上下文
+    let context = !config.json && (config.before_context > 0 || config.after_context > 0);
+    let output = Output::new(context);
+
+    // 使用 thread::scope 创建的线程可以借用 config、searcher 和 inputs，作用域结束前所有线程都会被 join
+    thread::scope(|s| {
+        for _ in 0..threads {
+            s.spawn(|| loop {
+                let index = next.fetch_add(1, Ordering::SeqCst);
+                if index >= inputs.len() || output.is_closed() {
+                    break;
+                }
+
+                let mut stream = Stream { output: &output, index, chunks: vec![] };
+                let result = search_input(config, searcher, &inputs[index], show_path, color, &mut stream);
+                let error = result.err().map(|e| format!("{}: {}", inputs[index].label(), e));
+                output.finish(index, stream.chunks, error);
+            });
+        }
+    });
+
+    output.state.into_inner().unwrap().failed
+}
+
+// -j 没有指定（为 0）时，使用 CPU 的并行度
+fn thread_count(threads: usize) -> usize {
+    if threads > 0 {
+        return threads;
+    }
+    thread::available_parallelism().map(|n| n.get()).unwrap_or(1)
+}
+
+// 一段输出，starts_group 表示它是某个 Printer 的第一段输出
+// 有上下文时，不同文件（以及 tar 归档中不同文件）的结果之间需要 --，只有写出的时候才知道前面有没有输出过内容
+struct Chunk {
+    starts_group: bool,
+    text: String,
+}
+
+// 所有工作线程共享的标准输出
+struct Output {
+    context: bool,
+    // 下一个轮到写入标准输出的输入，不用加锁就能判断是不是轮到自己了
+    next: AtomicUsize,
+    // 标准输出已经被关闭
+    closed: AtomicBool,
+    state: Mutex<OutputState>,
+}
+
+struct OutputState {
+    // 已经搜索完、但还没轮到的输入的输出和错误信息
+    finished: HashMap<usize, (Vec<Chunk>, Option<String>)>,
+    // 是否已经输出过内容，用来决定要不要输出 --
+    printed_any: bool,
+    // 无法读取的文件数量
+    failed: usize,
+}
+
+impl Output {
+    fn new(context: bool) -> Output {
+        Output {
+            context,
+            next: AtomicUsize::new(0),
+            closed: AtomicBool::new(false),
+            state: Mutex::new(OutputState { finished: HashMap::new(), printed_any: false, failed: 0 }),
+        }
+    }
+
+    fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::SeqCst)
+    }
+
+    // 轮到 index 时立即写出 chunks，否则什么也不做，chunks 留给之后再写
+    fn write(&self, index: usize, chunks: &mut Vec<Chunk>) {
+        if self.next.load(Ordering::SeqCst) != index {
+            return;
+        }
+        let mut state = self.state.lock().unwrap();
+        self.write_chunks(&mut state, chunks.drain(..));
+    }
+
+    // index 搜索结束：轮到它时写出剩下的输出，然后依次写出之后已经结束的输入；否则先暂存起来
+    fn finish(&self, index: usize, chunks: Vec<Chunk>, error: Option<String>) {
+        let mut state = self.state.lock().unwrap();
+        if self.next.load(Ordering::SeqCst) != index {
+            state.finished.insert(index, (chunks, error));
+            return;
+        }
+
+        let mut current = Some((chunks, error));
+        let mut index = index;
+        while let Some((chunks, error)) = current {
+            self.write_chunks(&mut state, chunks.into_iter());
+            if let Some(error) = error {
+                eprintln!("{}", error);
+                state.failed += 1;
+            }
+            index += 1;
+            self.next.store(index, Ordering::SeqCst);
+            current = state.finished.remove(&index);
+        }
+    }
+
+    // 调用者必须持有 state 的锁，并且已经轮到了这些输出
+    fn write_chunks(&self, state: &mut OutputState, chunks: impl Iterator<Item = Chunk>) {
+        if self.is_closed() {
+            return;
+        }
+        let mut stdout = io::stdout().lock();
+        for chunk in chunks {
+            let result = (|| {
+                if chunk.starts_group && self.context && state.printed_any {
+                    stdout.write_all(b"--\n")?;
+                }
+                stdout.write_all(chunk.text.as_bytes())
+            })();
+            state.printed_any = true;
+            if let Err(e) = result {
+                // 标准输出被关闭时安静地停止，其他错误和无法读取的文件一样报告出来
+                if e.kind() != io::ErrorKind::BrokenPipe {
+                    eprintln!("minigrep: failed to write output: {}", e);
+                    state.failed += 1;
+                }
+                self.closed.store(true, Ordering::SeqCst);
+                return;
+            }
+        }
+    }
+}
+
+// 一个输入的输出，轮到它时直接写入标准输出，没轮到时暂存在 chunks 中
+struct Stream<'a> {
+    output: &'a Output,
+    index: usize,
+    chunks: Vec<Chunk>,
+}
+
+impl Stream<'_> {
+    // 加入一段输出，返回是否应该继续搜索
+    fn push(&mut self, starts_group: bool, text: String) -> bool {
+        if !text.is_empty() {
+            match self.chunks.last_mut() {
+                // 暂存时把同一个 Printer 的输出合并起来，不用为每一行单独分配一个 Chunk
+                Some(last) if !starts_group => last.text.push_str(&text),
+                _ => self.chunks.push(Chunk { starts_group, text }),
+            }
+            self.output.write(self.index, &mut self.chunks);
+        }
+        !self.output.is_closed()
+    }
+}
+
+// 把 Printer 格式化好的内容及时交给 Stream，而不是等整个文件搜索完
+struct StreamSink<'a, 'b> {
+    printer: Printer,
+    stream: &'a mut Stream<'b>,
+    // 这个 Printer 是否已经输出过内容
+    started: bool,
+}
+
+impl<'a, 'b> StreamSink<'a, 'b> {
+    fn new(printer: Printer, stream: &'a mut Stream<'b>) -> StreamSink<'a, 'b> {
+        StreamSink { printer, stream, started: false }
+    }
+
+    // 把 Printer 中新的输出交给 Stream，返回是否应该继续搜索
+    fn flush(&mut self) -> bool {
+        let text = self.printer.take_output();
+        let starts_group = !self.started && !text.is_empty();
+        self.started |= starts_group;
+        self.stream.push(starts_group, text)
+    }
+
+    // 搜索结束：-c 和 -l 的结果由 finish 输出
+    fn finish(mut self) {
+        self.printer.finish();
+        self.flush();
+    }
+}
+
+impl Sink for StreamSink<'_, '_> {
+    fn matched(&mut self, m: &SinkMatch) -> io::Result<bool> {
+        let more = self.printer.matched(m)?;
+        Ok(self.flush() && more)
+    }
+
+    fn context(&mut self, context: &SinkContext) -> io::Result<bool> {
+        let more = self.printer.context(context)?;
+        Ok(self.flush() && more)
+    }
+
+    fn context_break(&mut self) -> io::Result<bool> {
+        let more = self.printer.context_break()?;
+        Ok(self.flush() && more)
+    }
+
+    fn binary(&mut self) -> io::Result<()> {
+        self.printer.binary()
+    }
+}
+
+// 搜索单个输入，输出交给 stream
+fn search_input(
+    config: &Config,
+    searcher: &Searcher,
+    input: &Input,
+    show_path: bool,
+    color: bool,
+    stream: &mut Stream,
+) -> io::Result<()> {
+    if config.in_place {
+        let output = rewrite_input(config, searcher, input)?;
+        stream.push(true, output);
+        return Ok(());
+    }
+
+    let mut archive = match input.open()? {
+        Source::Text(reader) => {
+            let printer = Printer::new(config, color, input.label(), show_path);
+            return search_with(searcher, StreamSink::new(printer, stream), reader);
+        }
+        Source::Tar(archive) => archive,
+    };
+
+    // tar 归档中的每个文件分别搜索，结果中的文件名显示为 归档:归档中的路径，例如 logs.tar:app/today.log
+    for entry in archive.entries()? {
+        if stream.output.is_closed() {
+            break;
+        }
+        let entry = entry?;
+        // 只搜索普通文件，跳过目录、链接等
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+
+        let label = format!("{}:{}", input.label(), entry.path()?.display());
+        let printer = Printer::new(config, color, label, true);
+        search_with(searcher, StreamSink::new(printer, stream), BufReader::new(entry))?;
+    }
+    Ok(())
+}
+
+// 搜索一个文件（或者标准输入），匹配、上下文和 -m 等限制都由 Searcher 处理，Printer 只负责格式化
+fn search_with<R: io::BufRead>(searcher: &Searcher, mut sink: StreamSink, reader: R) -> io::Result<()> {
+    searcher.search_reader(reader, &mut sink)?;
+    sink.finish();
+    Ok(())
+}
+
+// --in-place：改写文件，输出的是每个文件替换了多少次，例如 src/lib.rs: 3 replacements
+// 没有匹配的文件不会被改写，也不会输出
+fn rewrite_input(config: &Config, searcher: &Searcher, input: &Input) -> io::Result<String> {
+    let path = match input {
+        Input::File(path) => path,
+        Input::Stdin => return Err(io::Error::other("standard input can't be rewritten in place")),
+    };
+    let replacement = config.replace.as_deref().unwrap_or_default();
+
+    let count = replace::rewrite(config, &searcher.matcher, path, replacement)?;
+    Ok(match count {
+        0 => String::new(),
+        1 => format!("{}: 1 replacement\n", input.label()),
+        n => format!("{}: {} replacements\n", input.label(), n),
+    })
+}
@@ -0,0 +1,363 @@
+// 输出搜索结果，格式和 grep 保持一致：
+// 匹配的行使用 : 分隔前缀，例如 src/lib.rs:12:fn main() {
+// 上下文行使用 - 分隔前缀，例如 src/lib.rs-13-    let x = 1;
+// 不相邻的两组结果之间输出一行 --
+// 多个文件是并行搜索的，所以 Printer 不直接打印，而是把输出先写入缓冲区，再由 parallel 模块按文件的顺序写入标准输出
+// 匹配、上下文和 -m 都由 Searcher 处理，Printer 作为 Sink 接收它交出的结果，只负责格式化
+//
+// 另外还支持两种输出方式：
+// 1. 使用 ANSI 转义序列高亮匹配的内容，颜色和 grep 的默认配色一致
+// 2. --json：每个匹配输出一个 JSON 对象，方便编辑器等工具解析
+
+use std::fmt::Write;
+use std::io;
+use std::mem;
+
+use serde_json::json;
+
+use crate::matcher::Hit;
+use crate::searcher::{Sink, SinkContext, SinkMatch};
+use crate::Config;
+
+// ANSI 颜色：匹配内容为加粗红色，文件路径为紫色，行号为绿色，分隔符为青色
+const COLOR_MATCH: &str = "\x1b[1;31m";
+const COLOR_PATH: &str = "\x1b[35m";
+const COLOR_LINE_NUMBER: &str = "\x1b[32m";
+const COLOR_SEPARATOR: &str = "\x1b[36m";
+const COLOR_RESET: &str = "\x1b[0m";
+
+// --color 选项的取值
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColorChoice {
+    // 标准输出是终端时才使用颜色
+    Auto,
+    Always,
+    Never,
+}
+
+pub struct Printer {
+    line_number: bool,
+    byte_offset: bool,
+    color: bool,
+    json: bool,
+    // -c：只统计数量，最后由 finish 输出
+    count: bool,
+    // -l：只输出文件名，最后由 finish 输出
+    files_with_matches: bool,
+    // -U：-c 统计的是匹配的数量，而不是结果的数量
+    multiline: bool,
+    // --with-pattern：在匹配的行前面输出命中的模式
+    with_pattern: bool,
+    // 所有模式的原文，用于输出命中的模式
+    patterns: Vec<String>,
+    // 当前文件的名字，JSON 输出中总是需要它
+    path: String,
+    // 是否在每一行前面加上文件路径，只搜索单个文件时为 false
+    show_path: bool,
+    // -c 和 -l 使用的计数
+    matches: usize,
+    // 二进制文件没有被搜索，-c 不输出它的计数，否则 0 看起来像是搜索过但没有匹配
+    binary: bool,
+    // 还没有被取走的输出
+    out: String,
+}
+
+impl Printer {
+    // color 是已经根据 ColorChoice 和终端检测决定好的结果
+    pub fn new(config: &Config, color: bool, path: String, show_path: bool) -> Printer {
+        Printer {
+            line_number: config.line_number,
+            byte_offset: config.byte_offset,
+            color: color && !config.json,
+            json: config.json,
+            count: config.count,
+            files_with_matches: config.files_with_matches,
+            multiline: config.multiline,
+            with_pattern: config.with_pattern,
+            patterns: config.patterns.clone(),
+            path,
+            show_path,
+            matches: 0,
+            binary: false,
+            out: String::new(),
+        }
+    }
+
+    // 搜索结束之后调用：-c 输出计数，-l 在有匹配时输出文件名
+    pub fn finish(&mut self) {
+        if self.binary {
+            return;
+        }
+        if self.files_with_matches {
+            if self.matches > 0 {
+                self.path_only();
+            }
+        } else if self.count {
+            self.print_count();
+        }
+    }
+
+    // 取出到目前为止的输出，每次只会取到上一次取走之后新增的部分
+    pub fn take_output(&mut self) -> String {
+        mem::take(&mut self.out)
+    }
+
+    // -c 模式下只输出计数
+    fn print_count(&mut self) {
+        if self.json {
+            let value = json!({ "path": self.path, "count": self.matches });
+            writeln!(self.out, "{}", value).unwrap();
+            return;
+        }
+
+        if self.show_path {
+            self.prefix_path(':');
+        }
+        writeln!(self.out, "{}", self.matches).unwrap();
+    }
+
+    // -l 模式下只输出文件名
+    fn path_only(&mut self) {
+        if self.json {
+            let value = json!({ "path": self.path });
+            writeln!(self.out, "{}", value).unwrap();
+            return;
+        }
+
+        let path = self.path.clone();
+        self.paint(COLOR_PATH, &path);
+        self.out.push('\n');
+    }
+
+    // 每个匹配输出一个 JSON 对象，column 是匹配开始的位置（从 1 开始的字节列号），pattern 是命中的模式
+    // -v 选出的行没有匹配，column 和 pattern 为 null
+    // 多行模式下 line 和 end_line 是这个匹配开始和结束的行号，text 是匹配本身
+    fn print_json(&mut self, m: &SinkMatch) {
+        if m.hits.is_empty() {
+            let value = json!({ "path": self.path, "line": m.line_number, "column": null, "pattern": null, "text": m.line });
+            writeln!(self.out, "{}", value).unwrap();
+            return;
+        }
+
+        for hit in m.hits {
+            let value = if self.multiline {
+                // 匹配之前有几个换行符，它就从第几行之后开始
+                let before = &m.line[..hit.span.start];
+                let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+                let line = m.line_number + before.matches('\n').count();
+                json!({
+                    "path": self.path,
+                    "line": line,
+                    // 匹配最后一个字节所在的行，结尾的换行符属于它之前的那一行
+                    "end_line": line + m.line.as_bytes()[hit.span.start..hit.span.end.max(hit.span.start + 1) - 1]
+                        .iter()
+                        .filter(|&&b| b == b'\n')
+                        .count(),
+                    "column": hit.span.start - line_start + 1,
+                    "pattern": self.patterns[hit.pattern],
+                    "text": &m.line[hit.span.clone()],
+                })
+            } else {
+                json!({
+                    "path": self.path,
+                    "line": m.line_number,
+                    "column": hit.span.start + 1,
+                    "pattern": self.patterns[hit.pattern],
+                    "text": m.line,
+                })
+            };
+            writeln!(self.out, "{}", value).unwrap();
+        }
+    }
+
+    // 输出一行结果，多行模式下行号显示为 开始行-结束行，例如 poem.txt:3-5:...
+    fn print(&mut self, line_number: &str, byte_offset: usize, line: &str, separator: char, hits: &[Hit]) {
+        if self.show_path {
+            self.prefix_path(separator);
+        }
+        if self.line_number {
+            self.paint(COLOR_LINE_NUMBER, line_number);
+            self.paint(COLOR_SEPARATOR, &separator.to_string());
+        }
+        if self.byte_offset {
+            self.paint(COLOR_LINE_NUMBER, &byte_offset.to_string());
+            self.paint(COLOR_SEPARATOR, &separator.to_string());
+        }
+
+        // 命中的模式按照第一次出现的顺序输出，多个模式之间用 , 分隔
+        if self.with_pattern && !hits.is_empty() {
+            let mut seen: Vec<usize> = vec![];
+            for hit in hits {
+                if !seen.contains(&hit.pattern) {
+                    seen.push(hit.pattern);
+                }
+            }
+            let names: Vec<&str> = seen.iter().map(|&i| self.patterns[i].as_str()).collect();
+            let names = names.join(",");
+            self.paint(COLOR_MATCH, &names);
+            self.paint(COLOR_SEPARATOR, &separator.to_string());
+        }
+
+        // 依次输出匹配之间的普通文本和高亮的匹配内容
+        let mut last = 0;
+        for hit in hits {
+            self.out.push_str(&line[last..hit.span.start]);
+            self.paint(COLOR_MATCH, &line[hit.span.clone()]);
+            last = hit.span.end;
+        }
+        // 向 String 写入内容不会失败，所以这里可以直接 unwrap
+        writeln!(self.out, "{}", &line[last..]).unwrap();
+    }
+
+    fn prefix_path(&mut self, separator: char) {
+        let path = self.path.clone();
+        self.paint(COLOR_PATH, &path);
+        self.paint(COLOR_SEPARATOR, &separator.to_string());
+    }
+
+    // 开启颜色时用 ANSI 转义序列包裹文本，否则原样输出
+    fn paint(&mut self, color: &str, text: &str) {
+        // 空的匹配不需要着色
+        if self.color && !text.is_empty() {
+            write!(self.out, "{}{}{}", color, text, COLOR_RESET).unwrap();
+        } else {
+            self.out.push_str(text);
+        }
+    }
+}
+
+// 输出写入的是内存中的缓冲区，不会失败
+impl Sink for Printer {
+    fn matched(&mut self, m: &SinkMatch) -> io::Result<bool> {
+        if self.files_with_matches {
+            // -l 只需要知道有没有匹配，找到第一个匹配就可以停止读取了
+            self.matches += 1;
+            return Ok(false);
+        }
+        if self.count {
+            self.matches += if self.multiline { m.hits.len() } else { 1 };
+            return Ok(true);
+        }
+
+        if self.json {
+            self.print_json(m);
+        } else if m.line_number == m.end_line_number {
+            self.print(&m.line_number.to_string(), m.byte_offset, m.line, ':', m.hits);
+        } else {
+            let number = format!("{}-{}", m.line_number, m.end_line_number);
+            self.print(&number, m.byte_offset, m.line, ':', m.hits);
+        }
+        Ok(true)
+    }
+
+    fn context(&mut self, context: &SinkContext) -> io::Result<bool> {
+        self.print(&context.line_number.to_string(), context.byte_offset, context.line, '-', &[]);
+        Ok(true)
+    }
+
+    fn context_break(&mut self) -> io::Result<bool> {
+        self.paint(COLOR_SEPARATOR, "--");
+        self.out.push('\n');
+        Ok(true)
+    }
+
+    fn binary(&mut self) -> io::Result<()> {
+        self.binary = true;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn printer(args: &[&str], color: bool) -> Printer {
+        let args = args.iter().map(|s| s.to_string());
+        let config = crate::args::parse(args, false).unwrap();
+        Printer::new(&config, color, "poem.txt".to_string(), false)
+    }
+
+    fn matched(printer: &mut Printer, line_number: usize, end_line_number: usize, line: &str, hits: &[Hit]) {
+        let m = SinkMatch { line_number, end_line_number, byte_offset: 0, line, hits };
+        assert!(printer.matched(&m).unwrap());
+    }
+
+    #[test]
+    fn highlight_matches() {
+        let mut printer = printer(&["-n", "o", "poem.txt"], true);
+        matched(&mut printer, 6, 6, "a frog", &[Hit { span: 4..5, pattern: 0 }]);
+
+        assert_eq!(
+            "\x1b[32m6\x1b[0m\x1b[36m:\x1b[0ma fr\x1b[1;31mo\x1b[0mg\n",
+            printer.take_output()
+        );
+    }
+
+    #[test]
+    fn with_pattern() {
+        let mut printer = printer(&["--with-pattern", "-e", "o", "-e", "g", "poem.txt"], false);
+        let hits = [
+            Hit { span: 4..5, pattern: 0 },
+            Hit { span: 5..6, pattern: 1 },
+        ];
+        matched(&mut printer, 6, 6, "a frog", &hits);
+
+        assert_eq!("o,g:a frog\n", printer.take_output());
+    }
+
+    #[test]
+    fn context_lines() {
+        let mut printer = printer(&["-n", "-C1", "frog", "poem.txt"], false);
+        printer.context(&SinkContext { line_number: 5, byte_offset: 0, line: "How public," }).unwrap();
+        matched(&mut printer, 6, 6, "a frog", &[]);
+        printer.context_break().unwrap();
+
+        assert_eq!("5-How public,\n6:a frog\n--\n", printer.take_output());
+    }
+
+    #[test]
+    fn multiline_blocks() {
+        let block = "I'm nobody!\nWho are you?";
+        let mut text = printer(&["-nU", "you", "poem.txt"], false);
+        matched(&mut text, 1, 2, block, &[Hit { span: 8..19, pattern: 0 }]);
+        assert_eq!("1-2:I'm nobody!\nWho are you?\n", text.take_output());
+
+        let mut json = printer(&["--json", "-U", "you", "poem.txt"], false);
+        matched(&mut json, 1, 2, block, &[Hit { span: 8..19, pattern: 0 }]);
+        assert_eq!(
+            "{\"column\":9,\"end_line\":2,\"line\":1,\"path\":\"poem.txt\",\"pattern\":\"you\",\"text\":\"dy!\\nWho are\"}\n",
+            json.take_output()
+        );
+    }
+
+    #[test]
+    fn json_output() {
+        let mut printer = printer(&["--json", "o", "poem.txt"], true);
+        matched(&mut printer, 6, 6, "a frog", &[Hit { span: 4..5, pattern: 0 }]);
+
+        assert_eq!(
+            "{\"column\":5,\"line\":6,\"path\":\"poem.txt\",\"pattern\":\"o\",\"text\":\"a frog\"}\n",
+            printer.take_output()
+        );
+    }
+
+    #[test]
+    fn count_and_files() {
+        let mut count = printer(&["-c", "o", "poem.txt"], false);
+        matched(&mut count, 1, 1, "o", &[]);
+        matched(&mut count, 2, 2, "o", &[]);
+        count.finish();
+        assert_eq!("2\n", count.take_output());
+
+        let mut files = printer(&["-l", "o", "poem.txt"], false);
+        assert!(!files.matched(&SinkMatch { line_number: 1, end_line_number: 1, byte_offset: 0, line: "o", hits: &[] }).unwrap());
+        files.finish();
+        assert_eq!("poem.txt\n", files.take_output());
+
+        // 跳过的二进制文件没有计数
+        let mut binary = printer(&["-c", "o", "a.out"], false);
+        binary.binary().unwrap();
+        binary.finish();
+        assert_eq!("", binary.take_output());
+    }
+}
@@ -0,0 +1,124 @@
+// --replace --in-place：直接修改文件中的匹配
+// 为了不在写入过程中出错时留下写了一半的文件，先把替换之后的内容写入同一目录中的临时文件，再通过 rename 替换原文件
+// 同一个文件系统中的 rename 是原子的，其他程序看到的要么是原来的文件，要么是完整的新文件
+// 每一行原有的换行符（\n 或 \r\n）都会原样保留，没有匹配的文件不会被改写
+
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::archive;
+use crate::matcher::Matcher;
+use crate::Config;
+
+// 替换 path 中的所有匹配，返回替换的次数
+pub fn rewrite(config: &Config, matcher: &Matcher, path: &Path, replacement: &str) -> io::Result<usize> {
+    let bytes = fs::read(path)?;
+    // 压缩文件和归档写回时需要重新压缩、打包，这里不处理
+    // 搜索目录时经常会遇到它们，所以只提示一下然后跳过，而不是当作无法读取的文件
+    let name = archive::lowercase_name(Some(path));
+    if archive::is_gzip(&name, &bytes) || archive::is_tar(&name, &bytes) {
+        eprintln!("{}: compressed files and archives can't be rewritten in place, skipped", path.display());
+        return Ok(0);
+    }
+    // 搜索时跳过的二进制文件，替换时同样跳过
+    if !config.text && crate::is_binary(&bytes) {
+        return Ok(0);
+    }
+    // 搜索时非法的 UTF-8 会被替换为 U+FFFD，但写回时不能这样修改文件，所以要求整个文件都是合法的 UTF-8
+    let content = String::from_utf8(bytes)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "file is not valid UTF-8"))?;
+
+    // -m 限制的是有匹配的行数
+    let limit = config.max_count.unwrap_or(usize::MAX);
+    let mut replaced_lines = 0;
+    let mut replacements = 0;
+    let mut out = String::with_capacity(content.len());
+
+    for line in content.split_inclusive('\n') {
+        // 和 for_each_line 一样，匹配时不包括行尾的 \n 或 \r\n
+        let body = line.strip_suffix('\n').unwrap_or(line);
+        let body = body.strip_suffix('\r').unwrap_or(body);
+
+        let hits = if replaced_lines < limit { matcher.find_all(body) } else { vec![] };
+        if hits.is_empty() {
+            out.push_str(line);
+            continue;
+        }
+
+        replaced_lines += 1;
+        replacements += hits.len();
+        out.push_str(&matcher.replace(body, &hits, replacement).0);
+        out.push_str(&line[body.len()..]);
+    }
+
+    if replacements > 0 {
+        write_atomically(path, out.as_bytes())?;
+    }
+    Ok(replacements)
+}
+
+// 先写入临时文件，再重命名为 path，临时文件继承原文件的权限
+fn write_atomically(path: &Path, content: &[u8]) -> io::Result<()> {
+    let temp = temp_path(path);
+    let result = (|| {
+        let mut file = File::create(&temp)?;
+        file.write_all(content)?;
+        file.set_permissions(fs::metadata(path)?.permissions())?;
+        // 确保内容已经写入磁盘之后再替换原文件
+        file.sync_all()?;
+        fs::rename(&temp, path)
+    })();
+
+    // 出错时删除临时文件，删除失败也没有关系，返回的依然是原来的错误
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    result
+}
+
+// 临时文件和原文件放在同一个目录中，rename 才能保证是原子的，例如 src/.lib.rs.minigrep-1234-0
+// 进程号之后再加上一个递增的序号，同一个进程中的多个线程不会用到同一个临时文件
+fn temp_path(path: &Path) -> PathBuf {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    let name = path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+    let seq = NEXT.fetch_add(1, Ordering::Relaxed);
+    path.with_file_name(format!(".{}.minigrep-{}-{}", name, process::id(), seq))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(args: &[&str]) -> Config {
+        crate::args::parse(args.iter().map(|s| s.to_string()), false).unwrap()
+    }
+
+    #[test]
+    fn rewrite_keeps_line_endings() {
+        let dir = std::env::temp_dir().join(format!("minigrep-replace-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("lib.rs");
+        fs::write(&path, "add(1, add(2, 3));\r\nadd_one(4)\nlet x = add(5, 6);").unwrap();
+
+        let config = config(&["-w", "-r", "sum", "--in-place", "add", "f"]);
+        let matcher = Matcher::new(&config.searcher()).unwrap();
+        assert_eq!(3, rewrite(&config, &matcher, &path, "sum").unwrap());
+        assert_eq!("sum(1, sum(2, 3));\r\nadd_one(4)\nlet x = sum(5, 6);", fs::read_to_string(&path).unwrap());
+        // 没有留下临时文件
+        assert_eq!(1, fs::read_dir(&dir).unwrap().count());
+
+        // 每次调用使用不同的临时文件
+        assert_ne!(temp_path(&path), temp_path(&path));
+
+        // 压缩文件被跳过，不算作错误
+        let gz = dir.join("old.log.gz");
+        fs::write(&gz, b"\x1f\x8b add").unwrap();
+        assert_eq!(0, rewrite(&config, &matcher, &gz, "sum").unwrap());
+        assert_eq!(b"\x1f\x8b add".to_vec(), fs::read(&gz).unwrap());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
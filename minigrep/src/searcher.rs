@@ -0,0 +1,546 @@
+// 可以在其他程序中复用的搜索接口
+// Searcher 负责读取内容、匹配、处理上下文和 -m 之类的限制，找到的结果以事件的形式交给 Sink
+// Sink 决定怎样处理这些事件：命令行中的 Printer 把它们格式化后输出，调用者也可以收集、计数，或者写到任何地方
+//
+// let searcher = Searcher::builder().pattern("nobody").case(CaseMode::Insensitive).build()?;
+// let mut lines = vec![];
+// searcher.search_str(POEM, &mut |m: &SinkMatch| {
+//     lines.push(m.line_number);
+//     Ok(true)
+// })?;
+
+use std::collections::VecDeque;
+use std::error::Error;
+use std::io::{self, BufRead};
+
+use crate::case::CaseMode;
+use crate::matcher::{Boundary, Hit, Matcher};
+use crate::{for_each_line, is_binary};
+
+// 一个被选中的结果：普通模式下是一行，多行模式下是匹配涉及的连续几行
+#[derive(Debug)]
+pub struct SinkMatch<'a> {
+    // 开始和结束的行号（从 1 开始），只有多行模式下两者才可能不同
+    pub line_number: usize,
+    pub end_line_number: usize,
+    // 第一行在输入中的字节偏移量
+    pub byte_offset: usize,
+    // 行的内容，不包括行尾的换行符；多行模式下中间的行之间保留换行符
+    pub line: &'a str,
+    // 匹配在 line 中的位置，-v 选出的行没有匹配
+    pub hits: &'a [Hit],
+}
+
+// 一行上下文
+#[derive(Debug)]
+pub struct SinkContext<'a> {
+    pub line_number: usize,
+    pub byte_offset: usize,
+    pub line: &'a str,
+}
+
+// 接收搜索结果的事件，每个方法返回 Ok(false) 时停止搜索，返回错误时搜索也会停止并把错误返回给调用者
+pub trait Sink {
+    // 找到了一个结果
+    fn matched(&mut self, m: &SinkMatch) -> io::Result<bool>;
+
+    // 一行上下文，只有设置了上下文行数时才会出现
+    fn context(&mut self, _context: &SinkContext) -> io::Result<bool> {
+        Ok(true)
+    }
+
+    // 两组不相邻的结果之间的间隔，对应 grep 输出中的 --
+    fn context_break(&mut self) -> io::Result<bool> {
+        Ok(true)
+    }
+
+    // 输入被当作二进制文件跳过了，其中的内容没有被搜索
+    fn binary(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// 只关心匹配的调用者可以直接使用闭包作为 Sink
+impl<F> Sink for F
+where
+    F: FnMut(&SinkMatch) -> io::Result<bool>,
+{
+    fn matched(&mut self, m: &SinkMatch) -> io::Result<bool> {
+        self(m)
+    }
+}
+
+// Searcher 的构建器，所有选项的默认值和命令行一致：区分大小写的子串匹配，没有上下文，没有限制
+#[derive(Debug, Clone)]
+pub struct SearcherBuilder {
+    pub(crate) patterns: Vec<String>,
+    pub(crate) regex: bool,
+    case: CaseMode,
+    pub(crate) boundary: Boundary,
+    pub(crate) multiline: bool,
+    before_context: usize,
+    after_context: usize,
+    max_count: Option<usize>,
+    invert: bool,
+    text: bool,
+    replace: Option<String>,
+}
+
+impl SearcherBuilder {
+    // 添加一个模式，可以调用多次，任意一个模式匹配即可
+    pub fn pattern(mut self, pattern: impl Into<String>) -> SearcherBuilder {
+        self.patterns.push(pattern.into());
+        self
+    }
+
+    // 添加多个模式
+    pub fn patterns<I, P>(mut self, patterns: I) -> SearcherBuilder
+    where
+        I: IntoIterator<Item = P>,
+        P: Into<String>,
+    {
+        self.patterns.extend(patterns.into_iter().map(Into::into));
+        self
+    }
+
+    // 是否把模式当作正则表达式，对应 -E / -F
+    pub fn regex(mut self, yes: bool) -> SearcherBuilder {
+        self.regex = yes;
+        self
+    }
+
+    // 大小写模式，对应 -s / -i / -S
+    pub fn case(mut self, case: CaseMode) -> SearcherBuilder {
+        self.case = case;
+        self
+    }
+
+    // 只匹配完整的单词，对应 -w
+    pub fn word(mut self, yes: bool) -> SearcherBuilder {
+        self.boundary = toggle(self.boundary, Boundary::Word, yes);
+        self
+    }
+
+    // 只匹配完整的行，对应 -x；和 word 以最后设置的为准
+    pub fn whole_line(mut self, yes: bool) -> SearcherBuilder {
+        self.boundary = toggle(self.boundary, Boundary::Line, yes);
+        self
+    }
+
+    // 模式可以跨越换行符，对应 -U
+    pub fn multiline(mut self, yes: bool) -> SearcherBuilder {
+        self.multiline = yes;
+        self
+    }
+
+    // 匹配之前的上下文行数，对应 -B
+    pub fn before_context(mut self, lines: usize) -> SearcherBuilder {
+        self.before_context = lines;
+        self
+    }
+
+    // 匹配之后的上下文行数，对应 -A
+    pub fn after_context(mut self, lines: usize) -> SearcherBuilder {
+        self.after_context = lines;
+        self
+    }
+
+    // 同时设置前后的上下文行数，对应 -C
+    pub fn context(self, lines: usize) -> SearcherBuilder {
+        self.before_context(lines).after_context(lines)
+    }
+
+    // 每次搜索最多选出多少个结果，对应 -m
+    pub fn max_count(mut self, limit: Option<usize>) -> SearcherBuilder {
+        self.max_count = limit;
+        self
+    }
+
+    // 选出不匹配的行，对应 -v
+    pub fn invert(mut self, yes: bool) -> SearcherBuilder {
+        self.invert = yes;
+        self
+    }
+
+    // 把二进制内容也当作文本搜索，对应 -a；默认跳过开头包含 NUL 字节的内容
+    pub fn text(mut self, yes: bool) -> SearcherBuilder {
+        self.text = yes;
+        self
+    }
+
+    // --replace：Sink 收到的是替换之后的行，hits 是替换进去的文本的位置
+    pub(crate) fn replace(mut self, replacement: Option<String>) -> SearcherBuilder {
+        self.replace = replacement;
+        self
+    }
+
+    // 所有模式都确定之后，才能根据 smart case 决定是否区分大小写
+    pub(crate) fn case_sensitive(&self) -> bool {
+        self.case.is_case_sensitive(&self.patterns)
+    }
+
+    // 准备好匹配器，模式不合法（例如正则表达式无法编译）时返回错误
+    pub fn build(self) -> Result<Searcher, Box<dyn Error>> {
+        if self.multiline && self.invert {
+            return Err("multiline search can't be inverted".into());
+        }
+
+        let matcher = Matcher::new(&self)?;
+        Ok(Searcher {
+            matcher,
+            before: self.before_context,
+            after: self.after_context,
+            limit: self.max_count.unwrap_or(usize::MAX),
+            invert: self.invert,
+            multiline: self.multiline,
+            text: self.text,
+            replace: self.replace,
+        })
+    }
+}
+
+// word 和 whole_line 共用同一个设置，关闭时只有当前的设置是自己才会恢复为 None
+fn toggle(current: Boundary, boundary: Boundary, yes: bool) -> Boundary {
+    if yes {
+        boundary
+    } else if current == boundary {
+        Boundary::None
+    } else {
+        current
+    }
+}
+
+// 准备好的搜索器，可以在多个线程中同时使用，每次搜索的状态都保存在局部变量中
+pub struct Searcher {
+    pub(crate) matcher: Matcher,
+    before: usize,
+    after: usize,
+    limit: usize,
+    invert: bool,
+    multiline: bool,
+    text: bool,
+    replace: Option<String>,
+}
+
+impl Searcher {
+    pub fn builder() -> SearcherBuilder {
+        SearcherBuilder {
+            patterns: vec![],
+            regex: false,
+            case: CaseMode::Sensitive,
+            boundary: Boundary::None,
+            multiline: false,
+            before_context: 0,
+            after_context: 0,
+            max_count: None,
+            invert: false,
+            text: false,
+            replace: None,
+        }
+    }
+
+    // 搜索字符串
+    pub fn search_str<S: Sink>(&self, content: &str, sink: &mut S) -> io::Result<()> {
+        self.search_reader(content.as_bytes(), sink)
+    }
+
+    // 搜索任意实现了 BufRead 的来源，例如 BufReader<File> 或者标准输入
+    // 读取失败或者 Sink 返回错误时返回错误
+    pub fn search_reader<R: BufRead, S: Sink>(&self, mut reader: R, sink: &mut S) -> io::Result<()> {
+        // 二进制文件（图片、编译产物等）中的匹配通常没有意义，而且会在终端中输出乱码，默认跳过
+        if !self.text && is_binary(reader.fill_buf()?) {
+            return sink.binary();
+        }
+        if self.multiline {
+            return self.search_multiline(reader, sink);
+        }
+
+        let mut state = Context::new(self.before, self.after);
+        let mut selected_count = 0;
+        // for_each_line 的回调只能返回 bool，Sink 返回的错误先保存起来，读取结束后再返回
+        let mut error = None;
+
+        for_each_line(reader, |line_number, byte_offset, line| {
+            let result = (|| {
+                let context = SinkContext { line_number, byte_offset, line };
+
+                // 达到 -m 的限制之后，只把剩余的后置上下文输出完，然后停止读取
+                if selected_count >= self.limit {
+                    return if state.after_left > 0 { state.emit_context(sink, &context) } else { Ok(false) };
+                }
+
+                let hits = self.matcher.find_all(line);
+                // -v 时选出不匹配的行
+                if hits.is_empty() != self.invert {
+                    if state.after_left > 0 {
+                        return state.emit_context(sink, &context);
+                    }
+                    state.remember(&context);
+                    return Ok(true);
+                }
+                selected_count += 1;
+
+                // -v 选出的行没有匹配，也就不需要高亮
+                let hits = if self.invert { vec![] } else { hits };
+                // --replace：交给 Sink 的是替换之后的行
+                let replaced;
+                let (line, hits) = match &self.replace {
+                    Some(replacement) => {
+                        replaced = self.matcher.replace(line, &hits, replacement);
+                        (replaced.0.as_str(), &replaced.1[..])
+                    }
+                    None => (line, &hits[..]),
+                };
+                let m = SinkMatch { line_number, end_line_number: line_number, byte_offset, line, hits };
+                state.emit_match(sink, &m)
+            })();
+
+            result.unwrap_or_else(|e| {
+                error = Some(e);
+                false
+            })
+        })?;
+
+        error.map_or(Ok(()), Err)
+    }
+
+    // 多行模式：模式可能跨越多行，所以不能再逐行匹配，而是把全部内容读入内存之后一次性查找所有匹配
+    // 涉及相同行的匹配合并为一个结果，避免同一行被输出多次；-m 限制的是匹配的数量；不输出上下文
+    fn search_multiline<R: BufRead, S: Sink>(&self, mut reader: R, sink: &mut S) -> io::Result<()> {
+        let mut buf = vec![];
+        reader.read_to_end(&mut buf)?;
+        let content = String::from_utf8_lossy(&buf);
+
+        let mut hits = self.matcher.find_all(&content);
+        hits.truncate(self.limit);
+
+        // 每一行开头的字节偏移量
+        let starts: Vec<usize> = std::iter::once(0)
+            .chain(content.match_indices('\n').map(|(i, _)| i + 1).filter(|&i| i < content.len()))
+            .collect();
+        // 字节偏移量 pos 所在的行号（从 1 开始）
+        let line_of = |pos: usize| starts.partition_point(|&s| s <= pos).max(1);
+        // 匹配最后一个字节所在的行号，空的匹配就是它开始的那一行
+        let last_line_of = |hit: &Hit| line_of(hit.span.end.saturating_sub(1).max(hit.span.start));
+
+        let mut i = 0;
+        while i < hits.len() {
+            let first = line_of(hits[i].span.start);
+            let mut last = last_line_of(&hits[i]);
+            let mut j = i + 1;
+            while j < hits.len() && line_of(hits[j].span.start) <= last {
+                last = last.max(last_line_of(&hits[j]));
+                j += 1;
+            }
+
+            // 这一组匹配所在的完整行，去掉最后的 \n 或 \r\n
+            let block_start = starts[first - 1];
+            let block_end = starts.get(last).copied().unwrap_or(content.len());
+            let block = &content[block_start..block_end];
+            let block = block.strip_suffix('\n').unwrap_or(block);
+            let block = block.strip_suffix('\r').unwrap_or(block);
+
+            // 匹配的位置转换为相对于这一组开头的位置，匹配到的行尾换行符不属于这一组
+            // 开始位置也要限制在这一组之内：CRLF 的行中，$ 这样的空匹配落在 \r 和 \n 之间，已经超出了去掉 \r\n 的 block
+            let block_end = block_start + block.len();
+            let group: Vec<Hit> = hits[i..j].iter()
+                .map(|hit| Hit {
+                    span: hit.span.start.min(block_end) - block_start..hit.span.end.min(block_end) - block_start,
+                    pattern: hit.pattern,
+                })
+                .collect();
+
+            let m = SinkMatch {
+                line_number: first,
+                end_line_number: last,
+                byte_offset: block_start,
+                line: block,
+                hits: &group,
+            };
+            if !sink.matched(&m)? {
+                break;
+            }
+            i = j;
+        }
+        Ok(())
+    }
+}
+
+// 一次搜索中和上下文有关的状态
+struct Context {
+    before: usize,
+    after: usize,
+    // 前置上下文的缓冲区，只保留最近的 before 行：(行号, 字节偏移量, 内容)
+    buffer: VecDeque<(usize, usize, String)>,
+    // 还需要输出多少行后置上下文
+    after_left: usize,
+    // 最后一次交给 Sink 的行号，用来判断两组结果之间是否有间隔
+    last_emitted: Option<usize>,
+}
+
+impl Context {
+    fn new(before: usize, after: usize) -> Context {
+        Context { before, after, buffer: VecDeque::new(), after_left: 0, last_emitted: None }
+    }
+
+    // 没有被选中、也不是后置上下文的行，可能会成为下一个结果的前置上下文
+    fn remember(&mut self, context: &SinkContext) {
+        if self.before == 0 {
+            return;
+        }
+        self.buffer.push_back((context.line_number, context.byte_offset, context.line.to_string()));
+        if self.buffer.len() > self.before {
+            self.buffer.pop_front();
+        }
+    }
+
+    // 先交出缓冲区中的前置上下文，再交出结果，之后开始计算后置上下文
+    fn emit_match<S: Sink>(&mut self, sink: &mut S, m: &SinkMatch) -> io::Result<bool> {
+        while let Some((line_number, byte_offset, line)) = self.buffer.pop_front() {
+            if !self.emit_context(sink, &SinkContext { line_number, byte_offset, line: &line })? {
+                return Ok(false);
+            }
+        }
+        if !self.gap(sink, m.line_number)? {
+            return Ok(false);
+        }
+        self.after_left = self.after;
+        sink.matched(m)
+    }
+
+    fn emit_context<S: Sink>(&mut self, sink: &mut S, context: &SinkContext) -> io::Result<bool> {
+        self.after_left = self.after_left.saturating_sub(1);
+        if !self.gap(sink, context.line_number)? {
+            return Ok(false);
+        }
+        sink.context(context)
+    }
+
+    // 有上下文时，不相邻的两组结果之间需要一个间隔
+    fn gap<S: Sink>(&mut self, sink: &mut S, line_number: usize) -> io::Result<bool> {
+        let last = self.last_emitted.replace(line_number);
+        match last {
+            Some(last) if (self.before > 0 || self.after > 0) && line_number > last + 1 => sink.context_break(),
+            _ => Ok(true),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POEM: &str = "\
+I'm nobody! Who are you?
+Are you nobody, too?
+Then there's a pair of us - don't tell!
+They'd banish us, you know.
+
+How dreary to be somebody!
+How public, like a frog
+To tell your name the livelong day
+To an admiring bog!";
+
+    // 把所有事件记录为字符串：匹配为 行号:内容，上下文为 行号-内容，间隔为 --，跳过二进制文件为 binary
+    #[derive(Default)]
+    struct Events(Vec<String>);
+
+    impl Sink for Events {
+        fn matched(&mut self, m: &SinkMatch) -> io::Result<bool> {
+            self.0.push(format!("{}-{}:{}", m.line_number, m.end_line_number, m.line));
+            Ok(true)
+        }
+
+        fn context(&mut self, context: &SinkContext) -> io::Result<bool> {
+            self.0.push(format!("{}-{}", context.line_number, context.line));
+            Ok(true)
+        }
+
+        fn context_break(&mut self) -> io::Result<bool> {
+            self.0.push("--".to_string());
+            Ok(true)
+        }
+
+        fn binary(&mut self) -> io::Result<()> {
+            self.0.push("binary".to_string());
+            Ok(())
+        }
+    }
+
+    fn events(builder: SearcherBuilder, content: &str) -> Vec<String> {
+        let mut events = Events::default();
+        builder.build().unwrap().search_str(content, &mut events).unwrap();
+        events.0
+    }
+
+    #[test]
+    fn context_and_limits() {
+        let builder = Searcher::builder().pattern("tell").context(1);
+        assert_eq!(
+            vec![
+                "2-Are you nobody, too?",
+                "3-3:Then there's a pair of us - don't tell!",
+                "4-They'd banish us, you know.",
+                "--",
+                "7-How public, like a frog",
+                "8-8:To tell your name the livelong day",
+                "9-To an admiring bog!",
+            ],
+            events(builder, POEM)
+        );
+
+        let builder = Searcher::builder().pattern("NOBODY").case(CaseMode::Insensitive).max_count(Some(1)).after_context(1);
+        assert_eq!(vec!["1-1:I'm nobody! Who are you?", "2-Are you nobody, too?"], events(builder, POEM));
+    }
+
+    #[test]
+    fn closure_sink() {
+        let searcher = Searcher::builder().patterns(["frog", "bog"]).word(true).build().unwrap();
+        let mut lines = vec![];
+        searcher.search_str(POEM, &mut |m: &SinkMatch| {
+            lines.push(m.line_number);
+            Ok(true)
+        }).unwrap();
+        assert_eq!(vec![7, 9], lines);
+
+        // 返回 Ok(false) 时停止搜索
+        let mut count = 0;
+        searcher.search_str(POEM, &mut |_: &SinkMatch| {
+            count += 1;
+            Ok(false)
+        }).unwrap();
+        assert_eq!(1, count);
+    }
+
+    #[test]
+    fn multiline_groups() {
+        let builder = Searcher::builder().pattern(r"you\?\nAre|too").regex(true).multiline(true);
+        assert_eq!(
+            vec!["1-2:I'm nobody! Who are you?\nAre you nobody, too?"],
+            events(builder, POEM)
+        );
+
+        assert!(Searcher::builder().pattern("a").multiline(true).invert(true).build().is_err());
+    }
+
+    #[test]
+    fn multiline_crlf() {
+        // 落在 \r\n 中间和内容末尾的空匹配，位置被限制在去掉换行符的行之内
+        for pattern in ["$", "x*"] {
+            let searcher = Searcher::builder().pattern(pattern).regex(true).multiline(true).build().unwrap();
+            let mut results = vec![];
+            searcher.search_str("a\r\nb\r\n", &mut |m: &SinkMatch| {
+                assert!(m.hits.iter().all(|hit| hit.span.start <= hit.span.end && hit.span.end <= m.line.len()));
+                results.push(m.line.to_string());
+                Ok(true)
+            }).unwrap();
+            assert_eq!(vec!["a", "b"], results);
+        }
+    }
+
+    #[test]
+    fn binary_content() {
+        assert_eq!(vec!["binary"], events(Searcher::builder().pattern("frog"), "a frog\0"));
+        assert_eq!(vec!["1-1:a frog\0"], events(Searcher::builder().pattern("frog").text(true), "a frog\0"));
+    }
+}
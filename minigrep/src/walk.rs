@@ -0,0 +1,258 @@
+// 目录遍历：当 filename 是一个目录时，递归地找出需要搜索的所有文件
+// 和 ripgrep 一样，默认遵守遍历过程中遇到的 .gitignore 和 .ignore 文件，--no-ignore 可以关闭这个行为
+// 搜索的根目录在 git 仓库中时，根目录之上、直到仓库根目录的 ignore 文件也会生效，例如在仓库中搜索 crates/core
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use walkdir::{DirEntry, WalkDir};
+
+use crate::gitignore::Gitignore;
+
+// 默认跳过的目录名，cargo 的构建产物都在 target 中，搜索它们通常没有意义
+// 即使没有 .gitignore 也会跳过，--no-ignore 时不再跳过
+const SKIPPED_DIRS: [&str; 1] = ["target"];
+// 每个目录中会读取的 ignore 文件，后面的优先级更高：.ignore 中的规则可以覆盖 .gitignore
+const IGNORE_FILES: [&str; 2] = [".gitignore", ".ignore"];
+
+// 文件过滤器，由 --include 和 --exclude 两组 glob 模式组成
+// glob 匹配的是相对于搜索根目录的路径，例如 src/lib.rs
+// 由于 * 可以匹配路径分隔符，所以 *.rs 也能匹配到 src/lib.rs
+pub struct FileFilter {
+    include: Option<GlobSet>,
+    exclude: GlobSet,
+}
+
+impl FileFilter {
+    pub fn new(include: &[String], exclude: &[String]) -> Result<FileFilter, Box<dyn Error>> {
+        // 没有指定 --include 时，所有文件都会被包含，所以使用 None 表示“不过滤”
+        let include = if include.is_empty() {
+            None
+        } else {
+            Some(build_glob_set(include)?)
+        };
+
+        Ok(FileFilter {
+            include,
+            exclude: build_glob_set(exclude)?,
+        })
+    }
+
+    // 判断一个文件是否需要搜索：不能被 exclude 命中，并且（如果有 include）必须被 include 命中
+    pub fn is_match(&self, relative: &Path) -> bool {
+        if self.exclude.is_match(relative) {
+            return false;
+        }
+
+        match &self.include {
+            Some(include) => include.is_match(relative),
+            None => true,
+        }
+    }
+}
+
+fn build_glob_set(patterns: &[String]) -> Result<GlobSet, Box<dyn Error>> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        builder.add(Glob::new(pattern)?);
+    }
+    Ok(builder.build()?)
+}
+
+// 隐藏文件（目录）以 . 开头，target 目录是构建产物，默认都跳过
+fn is_skipped(entry: &DirEntry, use_ignore: bool) -> bool {
+    // 搜索的根目录本身不参与过滤，这样 minigrep query . 依然可以正常工作
+    if entry.depth() == 0 {
+        return false;
+    }
+
+    let name = entry.file_name().to_string_lossy();
+    if name.starts_with('.') {
+        return true;
+    }
+
+    use_ignore && entry.file_type().is_dir() && SKIPPED_DIRS.contains(&name.as_ref())
+}
+
+// 读取 dir 中的 ignore 文件，relative 是 dir 相对于搜索根目录的路径
+fn load_ignores(dir: &Path, relative: &Path) -> Vec<Rc<Gitignore>> {
+    IGNORE_FILES.iter()
+        .filter_map(|name| Gitignore::from_file(&dir.join(name), relative.to_path_buf(), PathBuf::new()))
+        .map(Rc::new)
+        .collect()
+}
+
+// 根目录之上的 ignore 文件，从外到内排列
+// 只有根目录位于某个 git 仓库中时才读取，并且不会超出仓库的根目录（包含 .git 的目录）
+fn outer_ignores(root: &Path) -> Vec<Rc<Gitignore>> {
+    let Ok(root) = root.canonicalize() else {
+        return vec![];
+    };
+    if root.join(".git").exists() {
+        return vec![];
+    }
+    let Some(repo) = root.ancestors().skip(1).find(|dir| dir.join(".git").exists()) else {
+        return vec![];
+    };
+
+    let mut ignores = vec![];
+    for dir in root.ancestors().skip(1) {
+        // prepend 是根目录相对于这个目录的路径，例如 crates/core
+        let prepend = root.strip_prefix(dir).unwrap_or(&root).to_path_buf();
+        let mut found: Vec<Rc<Gitignore>> = IGNORE_FILES.iter()
+            .filter_map(|name| Gitignore::from_file(&dir.join(name), PathBuf::new(), prepend.clone()))
+            .map(Rc::new)
+            .collect();
+        // 外层目录的规则优先级更低，放在前面
+        found.append(&mut ignores);
+        ignores = found;
+        if dir == repo {
+            break;
+        }
+    }
+    ignores
+}
+
+// 从最深的 ignore 文件开始检查，第一个有规则匹配的文件决定结果
+fn is_ignored(ignores: &[Rc<Gitignore>], relative: &Path, is_dir: bool) -> bool {
+    ignores.iter()
+        .rev()
+        .find_map(|ignore| ignore.matched(relative, is_dir))
+        .unwrap_or(false)
+}
+
+// 递归遍历 root，返回所有需要搜索的文件，以及遍历过程中出错的次数
+// 结果按照路径排序，保证每次运行输出的顺序都是一致的
+// use_ignore 为 false 时（--no-ignore）不读取任何 ignore 文件
+// 和 grep、ripgrep 一样，某个子目录无法读取（例如没有权限）时只打印错误信息，然后继续遍历其他目录
+pub fn collect_files(root: &Path, filter: &FileFilter, use_ignore: bool) -> (Vec<PathBuf>, usize) {
+    let mut files = vec![];
+    let mut errors = 0;
+
+    // 每个已经遍历到的目录对应的 ignore 文件，包括所有上层目录中的
+    // 遍历到一个目录时，在它父目录的基础上加上它自己的 ignore 文件
+    let mut ignores: HashMap<PathBuf, Vec<Rc<Gitignore>>> = HashMap::new();
+    if use_ignore {
+        let mut root_ignores = outer_ignores(root);
+        root_ignores.extend(load_ignores(root, Path::new("")));
+        ignores.insert(root.to_path_buf(), root_ignores);
+    }
+
+    let walker = WalkDir::new(root)
+        .sort_by_file_name()
+        .into_iter()
+        // filter_entry 返回 false 时，整个目录都不会再被深入遍历
+        .filter_entry(|entry| {
+            if entry.depth() == 0 {
+                return true;
+            }
+            if is_skipped(entry, use_ignore) {
+                return false;
+            }
+
+            let is_dir = entry.file_type().is_dir();
+            let relative = entry.path().strip_prefix(root).unwrap_or(entry.path());
+            // 目录也可以被 --exclude 排除，例如 --exclude 'tests'
+            if is_dir && filter.exclude.is_match(relative) {
+                return false;
+            }
+            if !use_ignore {
+                return true;
+            }
+
+            // 父目录一定已经遍历过了，所以它的 ignore 文件已经在 ignores 中
+            let parent = entry.path().parent().and_then(|p| ignores.get(p)).cloned().unwrap_or_default();
+            if is_ignored(&parent, relative, is_dir) {
+                return false;
+            }
+            if is_dir {
+                let mut own = parent;
+                own.extend(load_ignores(entry.path(), relative));
+                ignores.insert(entry.path().to_path_buf(), own);
+            }
+            true
+        });
+
+    for entry in walker {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                // 和搜索文件出错时的格式一样：路径: 错误信息
+                match (e.path(), e.io_error()) {
+                    (Some(path), Some(io_error)) => eprintln!("{}: {}", path.display(), io_error),
+                    _ => eprintln!("{}", e),
+                }
+                errors += 1;
+                continue;
+            }
+        };
+        if !entry.file_type().is_file() {
+            continue;
+        }
+
+        let relative = entry.path().strip_prefix(root).unwrap_or(entry.path());
+        if filter.is_match(relative) {
+            files.push(entry.into_path());
+        }
+    }
+
+    (files, errors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn include_and_exclude() {
+        let filter = FileFilter::new(
+            &["*.rs".to_string()],
+            &["tests/*".to_string()],
+        ).unwrap();
+
+        assert!(filter.is_match(Path::new("src/lib.rs")));
+        assert!(!filter.is_match(Path::new("poem.txt")));
+        assert!(!filter.is_match(Path::new("tests/cli.rs")));
+    }
+
+    #[test]
+    fn nested_ignore_files() {
+        let root = std::env::temp_dir().join(format!("minigrep-walk-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        for (path, content) in [
+            (".gitignore", "*.log\n!keep.log\nbuild/\n"),
+            ("a.log", ""),
+            ("keep.log", ""),
+            ("build/out.txt", ""),
+            ("target/debug.txt", ""),
+            ("src/lib.rs", ""),
+            ("src/.ignore", "generated.rs\n!keep.log\n"),
+            ("src/generated.rs", ""),
+            ("src/sub/.gitignore", "!*.log\n"),
+            ("src/sub/trace.log", ""),
+        ] {
+            let path = root.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        }
+
+        let filter = FileFilter::new(&[], &[]).unwrap();
+        let names = |use_ignore| -> Vec<String> {
+            collect_files(&root, &filter, use_ignore).0.iter()
+                .map(|p| p.strip_prefix(&root).unwrap().display().to_string())
+                .collect()
+        };
+
+        assert_eq!(vec!["keep.log", "src/lib.rs", "src/sub/trace.log"], names(true));
+        assert_eq!(
+            vec!["a.log", "build/out.txt", "keep.log", "src/generated.rs", "src/lib.rs", "src/sub/trace.log", "target/debug.txt"],
+            names(false)
+        );
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
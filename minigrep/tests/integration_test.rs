@@ -0,0 +1,149 @@
+// 集成测试：通过命令行运行编译好的 minigrep，检查 -v、-c、-l、-m、--replace 等输出模式
+// Cargo 会把二进制文件的路径放在 CARGO_BIN_EXE_<name> 环境变量中，所以测试中可以直接运行它
+// 测试使用项目根目录中的 poem.txt 作为搜索的文件，最后一个测试直接使用库中的 Searcher
+// streaming_output 通过管道和 minigrep 交互，检查输出是边搜索边写出的，以及标准输出被关闭时能正常退出
+
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Command, Stdio};
+
+use minigrep::{search, search_case_insensitive, CaseMode, Searcher, SinkMatch};
+
+const POEM: &str = include_str!("../poem.txt");
+
+// 在项目根目录中运行 minigrep，返回标准输出的内容
+fn minigrep(args: &[&str]) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_minigrep"))
+        .args(args)
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        // 避免环境中的 CASE_INSENSITIVE 影响测试结果
+        .env_remove("CASE_INSENSITIVE")
+        .output()
+        .expect("failed to run minigrep");
+
+    assert!(output.status.success(), "minigrep {:?} failed: {:?}", args, output);
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn invert_match() {
+    // poem.txt 中每一行都包含字母 o，所以 -v 不会选出任何行；而不存在的模式会选出所有行
+    assert_eq!("", minigrep(&["-v", "o", "poem.txt"]));
+    // poem.txt 的最后一行没有换行符，而输出的每一行都以换行符结尾
+    assert_eq!(format!("{}\n", POEM.trim_end()), minigrep(&["-v", "xyz", "poem.txt"]));
+    assert_eq!(
+        "Then there's a pair of us - don't tell!\nThey'd banish us, you know.\n",
+        minigrep(&["-vi", "-e", "how", "-e", "nobody", "-e", "to", "poem.txt"])
+    );
+}
+
+#[test]
+fn count() {
+    // 和库中的 search 函数得到的结果数量一致
+    assert_eq!(format!("{}\n", search("body", POEM).len()), minigrep(&["-c", "body", "poem.txt"]));
+    assert_eq!(
+        format!("{}\n", search_case_insensitive("how", POEM).len()),
+        minigrep(&["-ci", "how", "poem.txt"])
+    );
+    assert_eq!("5\n", minigrep(&["-cv", "body", "poem.txt"]));
+    assert_eq!("poem.txt:3\nCargo.toml:0\n", minigrep(&["-c", "body", "poem.txt", "Cargo.toml"]));
+}
+
+#[test]
+fn files_with_matches() {
+    assert_eq!("poem.txt\n", minigrep(&["-l", "frog", "poem.txt", "Cargo.toml"]));
+    assert_eq!("Cargo.toml\n", minigrep(&["-l", "minigrep", "poem.txt", "Cargo.toml"]));
+    assert_eq!("poem.txt\nCargo.toml\n", minigrep(&["-lv", "frog", "poem.txt", "Cargo.toml"]));
+}
+
+#[test]
+fn max_count() {
+    assert_eq!("I'm nobody! Who are you?\n", minigrep(&["-m", "1", "nobody", "poem.txt"]));
+    assert_eq!("2\n", minigrep(&["-c", "-m2", "body", "poem.txt"]));
+    assert_eq!("", minigrep(&["-m", "0", "nobody", "poem.txt"]));
+    // 达到上限之后，后置上下文依然会输出
+    assert_eq!(
+        "1:I'm nobody! Who are you?\n2-Are you nobody, too?\n",
+        minigrep(&["-n", "-m1", "-A1", "nobody", "poem.txt"])
+    );
+}
+
+#[test]
+fn replace() {
+    assert_eq!(
+        "1:I'm NOBODY! Who are you?\n2:Are you NOBODY, too?\n",
+        minigrep(&["-n", "-r", "NOBODY", "nobody", "poem.txt"])
+    );
+    assert_eq!("How somebody!\n", minigrep(&["-E", "-r", "$1 $2", r"^(How) dreary to be (\w+!)$", "poem.txt"]));
+
+    // --in-place 修改的是临时目录中的副本
+    let dir = std::env::temp_dir().join(format!("minigrep-in-place-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("poem.txt"), POEM).unwrap();
+    std::fs::write(dir.join("other.txt"), "no match here\n").unwrap();
+    let dir_arg = dir.to_str().unwrap();
+
+    assert_eq!(
+        format!("{}: 2 replacements\n", dir.join("poem.txt").display()),
+        minigrep(&["-w", "-r", "somebody", "--in-place", "nobody", dir_arg])
+    );
+    assert_eq!(POEM.replace("nobody", "somebody"), std::fs::read_to_string(dir.join("poem.txt")).unwrap());
+    assert_eq!("no match here\n", std::fs::read_to_string(dir.join("other.txt")).unwrap());
+
+    // 同一个文件出现两次时只改写一次，替换进去的文本中包含模式，改写两次就会多出一个 !
+    let poem = dir.join("poem.txt");
+    let poem_arg = poem.to_str().unwrap();
+    assert_eq!(
+        format!("{}: 3 replacements\n", poem.display()),
+        minigrep(&["-r", "somebody!", "--in-place", "somebody", poem_arg, dir_arg])
+    );
+    assert_eq!(
+        POEM.replace("nobody", "somebody").replace("somebody", "somebody!"),
+        std::fs::read_to_string(&poem).unwrap()
+    );
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn streaming_output() {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_minigrep"))
+        .args(["foo", "-"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("failed to run minigrep");
+    let mut stdin = child.stdin.take().unwrap();
+    let mut stdout = BufReader::new(child.stdout.take().unwrap());
+
+    // 标准输入还没有结束，匹配的行就已经输出了
+    stdin.write_all(b"foo\n").unwrap();
+    let mut line = String::new();
+    stdout.read_line(&mut line).unwrap();
+    assert_eq!("foo\n", line);
+
+    // 读取一行之后关闭标准输出，minigrep 应该正常退出而不是 panic
+    drop(stdout);
+    let _ = stdin.write_all("foo\n".repeat(100_000).as_bytes());
+    drop(stdin);
+    assert!(child.wait().unwrap().success());
+}
+
+#[test]
+fn library_api() {
+    let searcher = Searcher::builder()
+        .pattern("HOW")
+        .case(CaseMode::Insensitive)
+        .build()
+        .unwrap();
+
+    // 和命令行的结果一致
+    let mut lines = String::new();
+    searcher.search_str(POEM, &mut |m: &SinkMatch| {
+        lines.push_str(m.line);
+        lines.push('\n');
+        Ok(true)
+    }).unwrap();
+    assert_eq!(minigrep(&["-i", "HOW", "poem.txt"]), lines);
+
+    assert!(Searcher::builder().pattern("(").regex(true).build().is_err());
+}